@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+use crate::db::DEFAULT_CHECKPOINT_THRESHOLD;
+
+///When `RustyDb` folds the WAL into a fresh snapshot. A checkpoint happens once any
+///of the limits is passed, limits left at `None` are never checked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CheckpointPolicy {
+    ///checkpoint once more than this many WAL entries have been written
+    pub max_entries: Option<usize>,
+    ///checkpoint once the WAL file is at least this big
+    pub max_wal_bytes: Option<u64>,
+    ///checkpoint when there are new entries and this long has passed since the last one.
+    ///Checked on every write and by [`crate::RustyDb::checkpoint_if_due`].
+    pub max_interval: Option<Duration>,
+    ///encode and write the snapshot on another thread, so the write that triggers a
+    ///checkpoint only waits for the tables to be copied and the WAL to be swapped
+    pub background: bool,
+}
+
+impl Default for CheckpointPolicy {
+    fn default() -> Self {
+        Self {
+            max_entries: Some(DEFAULT_CHECKPOINT_THRESHOLD),
+            max_wal_bytes: None,
+            max_interval: None,
+            background: false,
+        }
+    }
+}
+
+impl CheckpointPolicy {
+    ///Only checkpoint when asked to
+    pub fn manual() -> Self {
+        Self {
+            max_entries: None,
+            ..Self::default()
+        }
+    }
+}
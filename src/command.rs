@@ -1,5 +1,16 @@
-use crate::err_types::ParseError;
+use std::{ops::Range, time::Duration};
 
+use crate::{
+    cursor::Cursor,
+    err_types::ParseError,
+    pattern::KeyPattern,
+    sql::{self, Statement},
+    tokenizer::{Token, tokenize_spanned},
+    value::Value,
+};
+
+///A parsed command, ready to be run with `RustyDb::execute`
+#[derive(Debug, Clone, PartialEq)]
 pub enum Command {
     Get {
         table: String,
@@ -8,95 +19,740 @@ pub enum Command {
     Put {
         table: String,
         key: String,
-        val: String,
+        val: Value,
+        ///expire the key this long after the write
+        ttl: Option<Duration>,
+        ///only write when this holds, SET NX/XX, SETNX and CAS
+        condition: PutCondition,
     },
     Del {
         table: String,
         key: String,
     },
+    ///Get several keys from one table
+    MGet {
+        table: String,
+        keys: Vec<String>,
+    },
+    ///Set several keys in one table as a single WAL record
+    MSet {
+        table: String,
+        pairs: Vec<(String, Value)>,
+    },
+    ///Delete several keys from one table as a single WAL record
+    MDel {
+        table: String,
+        keys: Vec<String>,
+    },
     CreateTable {
         table_name: String,
     },
     DropTable {
         table_name: String,
     },
+    RenameTable {
+        from: String,
+        to: String,
+    },
+    ///Remove every key but keep the table
+    TruncateTable {
+        table_name: String,
+    },
+    ///Copy a table into a new one
+    CopyTable {
+        from: String,
+        to: String,
+    },
+    ///Show a table's metadata
+    Describe {
+        table_name: String,
+    },
+    ///Change a table's options, `Some(None)` clears one and `None` leaves it alone
+    AlterTable {
+        table_name: String,
+        description: Option<Option<String>>,
+        default_ttl: Option<Option<Duration>>,
+    },
     ListTables,
+    ///Add `by` to an integer, INCR/DECR/INCRBY/DECRBY
+    Incr {
+        table: String,
+        key: String,
+        by: i64,
+    },
+    IncrFloat {
+        table: String,
+        key: String,
+        by: f64,
+    },
+    ///Expire a key after `ttl`. Like redis, zero expires it at once, deleting it.
+    Expire {
+        table: String,
+        key: String,
+        ttl: Duration,
+    },
+    ///Time left before a key expires
+    Ttl {
+        table: String,
+        key: String,
+    },
+    ///Remove a key's expiry
+    Persist {
+        table: String,
+        key: String,
+    },
+    ///Start buffering writes until COMMIT or ROLLBACK
+    Begin,
+    Commit,
+    Rollback,
+    ///Keys in `[from, to)` order, either end may be left open
+    Scan {
+        table: String,
+        from: Option<String>,
+        to: Option<String>,
+        limit: Option<usize>,
+    },
+    ///One page of an incremental scan, `SCAN table cursor [MATCH p] [COUNT n]`
+    ScanCursor {
+        table: String,
+        cursor: Cursor,
+        pattern: Option<KeyPattern>,
+        ///how many keys to look at, fewer may match
+        count: Option<usize>,
+    },
+    ///Keys starting with `prefix`, in order
+    Prefix {
+        table: String,
+        prefix: String,
+        limit: Option<usize>,
+    },
+    ///Keys matching a glob or `/regex/`, in order, continuing after the `after` key
+    Keys {
+        table: String,
+        pattern: Option<KeyPattern>,
+        limit: Option<usize>,
+        after: Option<String>,
+    },
+    ///Number of keys, optionally only those matching a pattern
+    Count {
+        table: String,
+        pattern: Option<KeyPattern>,
+    },
+    ///Database wide statistics
+    Info,
+    ///Fold the WAL into a fresh snapshot now
+    Checkpoint,
+    ///Remove expired keys, then checkpoint
+    Compact,
+    ///A SQL statement, see [`crate::sql`]
+    Sql(Statement),
+}
+
+impl Command {
+    ///The command's keyword, as counted in `RustyDb::stats`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Get { .. } => "GET",
+            Command::Put {
+                condition: PutCondition::IfEquals(_),
+                ..
+            } => "CAS",
+            Command::Put { .. } => "SET",
+            Command::Del { .. } => "DEL",
+            Command::MGet { .. } => "MGET",
+            Command::MSet { .. } => "MSET",
+            Command::MDel { .. } => "MDEL",
+            Command::CreateTable { .. } => "CREATE",
+            Command::DropTable { .. } => "DROP",
+            Command::RenameTable { .. } => "RENAME",
+            Command::TruncateTable { .. } => "TRUNCATE",
+            Command::CopyTable { .. } => "COPY",
+            Command::Describe { .. } => "DESCRIBE",
+            Command::AlterTable { .. } => "ALTER",
+            Command::ListTables => "LIST",
+            Command::Incr { .. } => "INCR",
+            Command::IncrFloat { .. } => "INCRBYFLOAT",
+            Command::Expire { .. } => "EXPIRE",
+            Command::Ttl { .. } => "TTL",
+            Command::Persist { .. } => "PERSIST",
+            Command::Begin => "BEGIN",
+            Command::Commit => "COMMIT",
+            Command::Rollback => "ROLLBACK",
+            Command::Scan { .. } | Command::ScanCursor { .. } => "SCAN",
+            Command::Prefix { .. } => "PREFIX",
+            Command::Keys { .. } => "KEYS",
+            Command::Count { .. } => "COUNT",
+            Command::Info => "INFO",
+            Command::Checkpoint => "CHECKPOINT",
+            Command::Compact => "COMPACT",
+            Command::Sql(statement) => statement.name(),
+        }
+    }
 }
 
-///TODO String or &str?
+///When a put is allowed to go ahead
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum PutCondition {
+    #[default]
+    Always,
+    ///the key must not exist, SET NX / SETNX
+    IfAbsent,
+    ///the key must already exist, SET XX
+    IfPresent,
+    ///the key must hold exactly this value, CAS
+    IfEquals(Value),
+}
+
+///Parse a line of input into a [`Command`]. Lines starting with SELECT, INSERT,
+///UPDATE or DELETE are SQL, see [`crate::sql`].
 pub fn parse(input: &str) -> Result<Command, ParseError> {
+    if sql::is_sql(input) {
+        return sql::parse(input).map(Command::Sql);
+    }
     //split into components (tokens)
-    let parts = input.trim().split_whitespace().collect::<Vec<&str>>();
+    let (tokens, spans): (Vec<Token>, Vec<Range<usize>>) =
+        tokenize_spanned(input)?.into_iter().unzip();
+    parse_tokens(&tokens).map_err(|e| match e.token {
+        //past the last token means something is missing at the end
+        Some(token) => {
+            let span = spans.get(token).cloned();
+            e.at_span(span.unwrap_or(input.len()..input.len()))
+        }
+        None => e,
+    })
+}
+
+///Parse already split tokens into a [`Command`], eg the arguments of a network request.
+///Errors say which token was at fault.
+pub fn parse_tokens(tokens: &[Token]) -> Result<Command, ParseError> {
+    //most arguments are names, only values care what kind of token they were
+    let parts: Vec<String> = tokens.iter().map(Token::text).collect();
+    let parts = parts.as_slice();
+    let value = |index: usize| tokens[index].to_value().map_err(|e| e.at_token(index));
     if parts.is_empty() {
-        return Err(ParseError::InvalidCommand("Empty command".to_owned()));
+        return Err(ParseError::invalid_command("Empty command".to_owned()));
     }
     //check command is first token
     let command = parts[0].to_uppercase();
     match command.as_str() {
         "GET" => {
-            check_len(&parts, 3, "GET requires 2 arguments, table and key")?;
-            return Ok(Command::Get {
-                table: parts[1].to_string(),
-                key: parts[2].to_string(),
-            });
+            check_len(parts, 3, "GET requires 2 arguments, table and key")?;
+            Ok(Command::Get {
+                table: parts[1].clone(),
+                key: parts[2].clone(),
+            })
         }
         "SET" => {
-            check_len(&parts, 4, "SET requires 3 arguments,table, key, val")?;
-            return Ok(Command::Put {
-                table: parts[1].to_string(),
-                key: parts[2].to_string(),
-                val: parts[3].to_string(),
-            });
+            check_min_len(parts, 4, "SET requires 3 arguments,table, key, val")?;
+            let (ttl, condition) = parse_set_options(&parts[4..]).map_err(|e| e.shifted(4))?;
+            Ok(Command::Put {
+                table: parts[1].clone(),
+                key: parts[2].clone(),
+                val: value(3)?,
+                ttl,
+                condition,
+            })
+        }
+        "SETNX" => {
+            check_len(parts, 4, "SETNX requires 3 arguments, table, key, val")?;
+            Ok(Command::Put {
+                table: parts[1].clone(),
+                key: parts[2].clone(),
+                val: value(3)?,
+                ttl: None,
+                condition: PutCondition::IfAbsent,
+            })
+        }
+        "CAS" => {
+            check_len(
+                parts,
+                5,
+                "CAS requires 4 arguments, table, key, expected, new",
+            )?;
+            Ok(Command::Put {
+                table: parts[1].clone(),
+                key: parts[2].clone(),
+                val: value(4)?,
+                ttl: None,
+                condition: PutCondition::IfEquals(value(3)?),
+            })
+        }
+        "INCR" | "DECR" => {
+            check_len(
+                parts,
+                3,
+                &format!("{command} requires 2 arguments, table and key"),
+            )?;
+            Ok(Command::Incr {
+                table: parts[1].clone(),
+                key: parts[2].clone(),
+                by: if command == "INCR" { 1 } else { -1 },
+            })
+        }
+        "INCRBY" | "DECRBY" => {
+            check_len(
+                parts,
+                4,
+                &format!("{command} requires 3 arguments, table, key, amount"),
+            )?;
+            let by = parse_integer(&parts[3], &command).map_err(|e| e.at_token(3))?;
+            let by = if command == "INCRBY" {
+                by
+            } else {
+                by.checked_neg().ok_or_else(|| {
+                    ParseError::invalid_command(format!("DECRBY amount out of range: {by}"))
+                        .at_token(3)
+                })?
+            };
+            Ok(Command::Incr {
+                table: parts[1].clone(),
+                key: parts[2].clone(),
+                by,
+            })
+        }
+        "INCRBYFLOAT" => {
+            check_len(
+                parts,
+                4,
+                "INCRBYFLOAT requires 3 arguments, table, key, amount",
+            )?;
+            let by = parts[3]
+                .parse::<f64>()
+                .ok()
+                .filter(|by| by.is_finite())
+                .ok_or_else(|| {
+                    ParseError::invalid_command(format!(
+                        "INCRBYFLOAT expects a number, got: {}",
+                        parts[3]
+                    ))
+                    .at_token(3)
+                })?;
+            Ok(Command::IncrFloat {
+                table: parts[1].clone(),
+                key: parts[2].clone(),
+                by,
+            })
+        }
+        "EXPIRE" => {
+            check_len(parts, 4, "EXPIRE requires 3 arguments, table, key, seconds")?;
+            Ok(Command::Expire {
+                table: parts[1].clone(),
+                key: parts[2].clone(),
+                ttl: Duration::from_secs(
+                    parse_number(&parts[3], "EXPIRE seconds").map_err(|e| e.at_token(3))?,
+                ),
+            })
+        }
+        "TTL" => {
+            check_len(parts, 3, "TTL requires 2 arguments, table and key")?;
+            Ok(Command::Ttl {
+                table: parts[1].clone(),
+                key: parts[2].clone(),
+            })
+        }
+        "PERSIST" => {
+            check_len(parts, 3, "PERSIST requires 2 arguments, table and key")?;
+            Ok(Command::Persist {
+                table: parts[1].clone(),
+                key: parts[2].clone(),
+            })
         }
         "DEL" => {
-            check_len(&parts, 3, "DEL requires 2 arguments,table, key")?;
-            return Ok(Command::Del {
-                table: parts[1].to_string(),
-                key: parts[2].to_string(),
-            });
+            check_len(parts, 3, "DEL requires 2 arguments,table, key")?;
+            Ok(Command::Del {
+                table: parts[1].clone(),
+                key: parts[2].clone(),
+            })
+        }
+        "MGET" | "MDEL" => {
+            check_min_len(
+                parts,
+                3,
+                &format!("{command} requires a table and at least 1 key"),
+            )?;
+            let (table, keys) = (parts[1].clone(), parts[2..].to_vec());
+            if command == "MGET" {
+                Ok(Command::MGet { table, keys })
+            } else {
+                Ok(Command::MDel { table, keys })
+            }
+        }
+        "MSET" => {
+            check_min_len(parts, 4, "MSET requires a table and key value pairs")?;
+            if !parts[2..].len().is_multiple_of(2) {
+                return Err(ParseError::wrong_arguments(
+                    "MSET requires key value pairs, a value is missing".to_string(),
+                )
+                .at_token(parts.len()));
+            }
+            let pairs = (2..tokens.len())
+                .step_by(2)
+                .map(|at| Ok((parts[at].clone(), value(at + 1)?)))
+                .collect::<Result<_, ParseError>>()?;
+            Ok(Command::MSet {
+                table: parts[1].clone(),
+                pairs,
+            })
         }
         "CREATE" => {
-            check_len(&parts, 2, "CREATE requires 1 arguments,table_name")?;
-            return Ok(Command::CreateTable {
-                table_name: parts[1].to_string(),
-            });
+            check_len(parts, 2, "CREATE requires 1 arguments,table_name")?;
+            Ok(Command::CreateTable {
+                table_name: parts[1].clone(),
+            })
         }
         "DROP" => {
-            check_len(&parts, 2, "DROP requires 1 arguments,table_name")?;
-            return Ok(Command::DropTable {
-                table_name: parts[1].to_string(),
-            });
+            check_len(parts, 2, "DROP requires 1 arguments,table_name")?;
+            Ok(Command::DropTable {
+                table_name: parts[1].clone(),
+            })
+        }
+        "RENAME" => {
+            check_len(
+                parts,
+                3,
+                "RENAME requires 2 arguments, old and new table names",
+            )?;
+            Ok(Command::RenameTable {
+                from: parts[1].clone(),
+                to: parts[2].clone(),
+            })
+        }
+        "TRUNCATE" => {
+            check_len(parts, 2, "TRUNCATE requires 1 arguments,table_name")?;
+            Ok(Command::TruncateTable {
+                table_name: parts[1].clone(),
+            })
+        }
+        "COPY" => {
+            check_len(
+                parts,
+                3,
+                "COPY requires 2 arguments, source and new table names",
+            )?;
+            Ok(Command::CopyTable {
+                from: parts[1].clone(),
+                to: parts[2].clone(),
+            })
+        }
+        "DESCRIBE" => {
+            check_len(parts, 2, "DESCRIBE requires 1 arguments,table_name")?;
+            Ok(Command::Describe {
+                table_name: parts[1].clone(),
+            })
+        }
+        "ALTER" => {
+            check_min_len(
+                parts,
+                4,
+                "ALTER requires a table and DESCRIPTION text or TTL secs",
+            )?;
+            let mut description = None;
+            let mut default_ttl = None;
+            for (at, keyword, val) in keyword_args(parts, 2, "ALTER")? {
+                //NONE clears the option
+                let cleared = val.eq_ignore_ascii_case("NONE");
+                match keyword.as_str() {
+                    "DESCRIPTION" => description = Some((!cleared).then(|| val.clone())),
+                    "TTL" if cleared => default_ttl = Some(None),
+                    "TTL" => {
+                        let secs = parse_number(val, "TTL").map_err(|e| e.at_token(at + 1))?;
+                        default_ttl = Some(Some(Duration::from_secs(secs)))
+                    }
+                    other => {
+                        return Err(ParseError::invalid_command(format!(
+                            "Unknown ALTER option: {other}, expected DESCRIPTION or TTL"
+                        ))
+                        .at_token(at));
+                    }
+                }
+            }
+            Ok(Command::AlterTable {
+                table_name: parts[1].clone(),
+                description,
+                default_ttl,
+            })
         }
         "LIST" => {
-            check_len(&parts, 1, "LIST requires no arguments")?;
-            return Ok(Command::ListTables {});
+            check_len(parts, 1, "LIST requires no arguments")?;
+            Ok(Command::ListTables)
+        }
+        "INFO" => {
+            check_len(parts, 1, "INFO requires no arguments")?;
+            Ok(Command::Info)
+        }
+        "CHECKPOINT" => {
+            check_len(parts, 1, "CHECKPOINT requires no arguments")?;
+            Ok(Command::Checkpoint)
+        }
+        "COMPACT" => {
+            check_len(parts, 1, "COMPACT requires no arguments")?;
+            Ok(Command::Compact)
+        }
+        "BEGIN" => {
+            check_len(parts, 1, "BEGIN requires no arguments")?;
+            Ok(Command::Begin)
+        }
+        "COMMIT" => {
+            check_len(parts, 1, "COMMIT requires no arguments")?;
+            Ok(Command::Commit)
         }
-        other => {
-            return Err(ParseError::InvalidCommand(format!(
-                "Uknown command: {other}"
-            )));
+        "ROLLBACK" => {
+            check_len(parts, 1, "ROLLBACK requires no arguments")?;
+            Ok(Command::Rollback)
         }
+        "SCAN" => {
+            check_min_len(parts, 2, "SCAN requires at least 1 argument, table")?;
+            //a cursor can't be mistaken for FROM, TO or LIMIT
+            if let Some(Ok(cursor)) = parts.get(2).map(|cursor| Cursor::parse(cursor)) {
+                let (pattern, count) = parse_scan_options(&parts[3..]).map_err(|e| e.shifted(3))?;
+                return Ok(Command::ScanCursor {
+                    table: parts[1].clone(),
+                    cursor,
+                    pattern,
+                    count,
+                });
+            }
+            let mut from = None;
+            let mut to = None;
+            let mut limit = None;
+            for (at, keyword, val) in keyword_args(parts, 2, "SCAN")? {
+                match keyword.as_str() {
+                    "FROM" => from = Some(val.clone()),
+                    "TO" => to = Some(val.clone()),
+                    "LIMIT" => limit = Some(parse_limit(val).map_err(|e| e.at_token(at + 1))?),
+                    other => {
+                        return Err(ParseError::invalid_command(format!(
+                            "Unknown SCAN option: {other}, expected FROM, TO or LIMIT"
+                        ))
+                        .at_token(at));
+                    }
+                }
+            }
+            Ok(Command::Scan {
+                table: parts[1].clone(),
+                from,
+                to,
+                limit,
+            })
+        }
+        "PREFIX" => {
+            check_min_len(parts, 3, "PREFIX requires 2 arguments, table and prefix")?;
+            let mut limit = None;
+            for (at, keyword, val) in keyword_args(parts, 3, "PREFIX")? {
+                match keyword.as_str() {
+                    "LIMIT" => limit = Some(parse_limit(val).map_err(|e| e.at_token(at + 1))?),
+                    other => {
+                        return Err(ParseError::invalid_command(format!(
+                            "Unknown PREFIX option: {other}, expected LIMIT"
+                        ))
+                        .at_token(at));
+                    }
+                }
+            }
+            Ok(Command::Prefix {
+                table: parts[1].clone(),
+                prefix: parts[2].clone(),
+                limit,
+            })
+        }
+        "KEYS" => {
+            check_min_len(parts, 2, "KEYS requires at least 1 argument, table")?;
+            //an odd number of trailing parts means the first one is the pattern
+            let (pattern, options_from) = match &parts[2..] {
+                [pattern, options @ ..] if options.len().is_multiple_of(2) => {
+                    let pattern = KeyPattern::parse(pattern).map_err(|e| e.at_token(2))?;
+                    (Some(pattern), 3)
+                }
+                _ => (None, 2),
+            };
+            let mut limit = None;
+            let mut after = None;
+            for (at, keyword, val) in keyword_args(parts, options_from, "KEYS")? {
+                match keyword.as_str() {
+                    "LIMIT" => limit = Some(parse_limit(val).map_err(|e| e.at_token(at + 1))?),
+                    "AFTER" => after = Some(val.clone()),
+                    other => {
+                        return Err(ParseError::invalid_command(format!(
+                            "Unknown KEYS option: {other}, expected LIMIT or AFTER"
+                        ))
+                        .at_token(at));
+                    }
+                }
+            }
+            Ok(Command::Keys {
+                table: parts[1].clone(),
+                pattern,
+                limit,
+                after,
+            })
+        }
+        "COUNT" => {
+            if !(2..=3).contains(&parts.len()) {
+                return Err(ParseError::wrong_arguments(format!(
+                    "COUNT requires a table and an optional pattern! actual-> {}",
+                    parts.len()
+                ))
+                .at_token(parts.len().min(3)));
+            }
+            Ok(Command::Count {
+                table: parts[1].clone(),
+                pattern: parts
+                    .get(2)
+                    .map(|p| KeyPattern::parse(p).map_err(|e| e.at_token(2)))
+                    .transpose()?,
+            })
+        }
+        other => Err(ParseError::invalid_command(format!("Uknown command: {other}")).at_token(0)),
     }
-
-    //get datastore
-    //return value or error if not present
 }
 
-fn check_len(parts: &[&str], expected_num: usize, err_msg: &str) -> Result<(), ParseError> {
+///Blames the first extra argument, or the end of the line if one is missing
+fn check_len(parts: &[String], expected_num: usize, err_msg: &str) -> Result<(), ParseError> {
     if parts.len() != expected_num {
-        return Err(ParseError::WrongNumberOfArguments(format!(
-            "{}! actual-> {}",
-            err_msg,
-            parts.len()
-        )));
+        return Err(
+            ParseError::wrong_arguments(format!("{}! actual-> {}", err_msg, parts.len()))
+                .at_token(parts.len().min(expected_num)),
+        );
+    }
+    Ok(())
+}
+
+fn check_min_len(parts: &[String], min_num: usize, err_msg: &str) -> Result<(), ParseError> {
+    if parts.len() < min_num {
+        return Err(
+            ParseError::wrong_arguments(format!("{}! actual-> {}", err_msg, parts.len()))
+                .at_token(parts.len()),
+        );
     }
     Ok(())
 }
 
+///Pair up the `KEYWORD value` options from `parts[from..]`, keywords are uppercased.
+///Each pair comes with the index of its keyword.
+fn keyword_args<'a>(
+    parts: &'a [String],
+    from: usize,
+    command: &str,
+) -> Result<Vec<(usize, String, &'a String)>, ParseError> {
+    if !(parts.len() - from).is_multiple_of(2) {
+        return Err(ParseError::wrong_arguments(format!(
+            "{command} options must be KEYWORD value pairs"
+        ))
+        .at_token(parts.len()));
+    }
+    Ok((from..parts.len())
+        .step_by(2)
+        .map(|at| (at, parts[at].to_uppercase(), &parts[at + 1]))
+        .collect())
+}
+
+fn parse_limit(val: &str) -> Result<usize, ParseError> {
+    val.parse::<usize>()
+        .map_err(|_| ParseError::invalid_command(format!("LIMIT expects a number, got: {val}")))
+}
+
+fn parse_number(val: &str, what: &str) -> Result<u64, ParseError> {
+    val.parse::<u64>()
+        .map_err(|_| ParseError::invalid_command(format!("{what} expects a number, got: {val}")))
+}
+
+fn parse_integer(val: &str, what: &str) -> Result<i64, ParseError> {
+    val.parse::<i64>()
+        .map_err(|_| ParseError::invalid_command(format!("{what} expects an integer, got: {val}")))
+}
+
+///Options after a SCAN cursor: `MATCH pattern` and `COUNT n`
+pub fn parse_scan_options(
+    options: &[String],
+) -> Result<(Option<KeyPattern>, Option<usize>), ParseError> {
+    let mut pattern = None;
+    let mut count = None;
+    for (at, keyword, val) in keyword_args(options, 0, "SCAN")? {
+        match keyword.as_str() {
+            "MATCH" => pattern = Some(KeyPattern::parse(val).map_err(|e| e.at_token(at + 1))?),
+            "COUNT" => {
+                count = Some(val.parse::<usize>().map_err(|_| {
+                    ParseError::invalid_command(format!("COUNT expects a number, got: {val}"))
+                        .at_token(at + 1)
+                })?)
+            }
+            other => {
+                return Err(ParseError::invalid_command(format!(
+                    "Unknown SCAN option: {other}, expected MATCH or COUNT"
+                ))
+                .at_token(at));
+            }
+        }
+    }
+    Ok((pattern, count))
+}
+
+///Options after `SET table key val`: `EX seconds` or `PX millis`, and `NX` or `XX`.
+///Returns the ttl and the condition the write depends on.
+pub fn parse_set_options(
+    options: &[String],
+) -> Result<(Option<Duration>, PutCondition), ParseError> {
+    let mut ttl = None;
+    let mut condition = PutCondition::Always;
+    let len = options.len();
+    let mut options = options.iter().enumerate();
+    while let Some((at, option)) = options.next() {
+        let keyword = option.to_uppercase();
+        match keyword.as_str() {
+            "NX" | "XX" => {
+                if condition != PutCondition::Always {
+                    return Err(ParseError::invalid_command(
+                        "SET takes only one of NX or XX".to_string(),
+                    )
+                    .at_token(at));
+                }
+                condition = if keyword == "NX" {
+                    PutCondition::IfAbsent
+                } else {
+                    PutCondition::IfPresent
+                };
+            }
+            "EX" | "PX" => {
+                let (_, val) = options.next().ok_or_else(|| {
+                    ParseError::wrong_arguments(format!("SET {keyword} requires a value"))
+                        .at_token(len)
+                })?;
+                let amount = parse_number(val, &keyword).map_err(|e| e.at_token(at + 1))?;
+                if amount == 0 {
+                    //the key would be expired before anyone could read it, redis refuses too
+                    return Err(ParseError::invalid_token(format!(
+                        "Invalid expire time for SET {keyword}, must be positive"
+                    ))
+                    .at_token(at + 1));
+                }
+                let expiry = if keyword == "EX" {
+                    Duration::from_secs(amount)
+                } else {
+                    Duration::from_millis(amount)
+                };
+                if ttl.replace(expiry).is_some() {
+                    return Err(ParseError::invalid_command(
+                        "SET takes only one of EX or PX".to_string(),
+                    )
+                    .at_token(at));
+                }
+            }
+            other => {
+                return Err(ParseError::invalid_command(format!(
+                    "Unknown SET option: {other}, expected EX, PX, NX or XX"
+                ))
+                .at_token(at));
+            }
+        }
+    }
+    Ok((ttl, condition))
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use crate::err_types::ParseErrorKind;
 
     #[test]
     fn test_parse_get() {
@@ -111,4 +767,401 @@ mod tests {
             panic!("Expected Command::Get from {}", input);
         }
     }
+
+    #[test]
+    fn test_parse_set_quoted_value() {
+        let input = r#"SET users u1 "Alice Smith""#;
+        match parse(input) {
+            Ok(Command::Put {
+                table,
+                key,
+                val,
+                ttl: None,
+                condition: PutCondition::Always,
+            }) => {
+                assert_eq!("users", table);
+                assert_eq!("u1", key);
+                assert_eq!(val, "Alice Smith");
+            }
+            _ => panic!("Expected Command::Put from {}", input),
+        }
+    }
+
+    #[test]
+    fn test_parse_scan() {
+        let input = "scan events from 2024-01 to 2024-02 limit 10";
+        assert_eq!(
+            parse(input),
+            Ok(Command::Scan {
+                table: "events".to_string(),
+                from: Some("2024-01".to_string()),
+                to: Some("2024-02".to_string()),
+                limit: Some(10),
+            })
+        );
+        assert!(matches!(
+            parse("SCAN events LIMIT ten"),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidCommand,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("SCAN events FROM"),
+            Err(ParseError {
+                kind: ParseErrorKind::WrongNumberOfArguments,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_parse_prefix() {
+        assert_eq!(
+            parse("PREFIX users user:"),
+            Ok(Command::Prefix {
+                table: "users".to_string(),
+                prefix: "user:".to_string(),
+                limit: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_typed_values() {
+        let val_of = |input: &str| match parse(input) {
+            Ok(Command::Put { val, .. }) => val,
+            other => panic!("Expected Command::Put from {}, got {:?}", input, other),
+        };
+        assert_eq!(val_of("SET t k 42"), Value::Int(42));
+        assert_eq!(val_of("SET t k '42'"), Value::from("42"));
+        assert_eq!(val_of("SET t k -1.5"), Value::Float(-1.5));
+        assert_eq!(val_of("SET t k false"), Value::Bool(false));
+        assert_eq!(val_of("SET t k x'00ff'"), Value::Bytes(vec![0x00, 0xff]));
+        assert_eq!(
+            val_of(r#"SET t k [1, "a b"] EX 10"#),
+            Value::List(vec![Value::Int(1), Value::from("a b")])
+        );
+        assert!(parse("SET t k [1, 2").is_err());
+    }
+
+    #[test]
+    fn test_parse_set_with_expiry() {
+        assert_eq!(
+            parse("SET sessions s1 token EX 60"),
+            Ok(Command::Put {
+                table: "sessions".to_string(),
+                key: "s1".to_string(),
+                val: Value::from("token"),
+                ttl: Some(Duration::from_secs(60)),
+                condition: PutCondition::Always,
+            })
+        );
+        assert!(matches!(
+            parse("SET sessions s1 token PX 10 EX 60"),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidCommand,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("SET sessions s1 token EX soon"),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidCommand,
+                ..
+            })
+        ));
+        for zero in ["EX 0", "PX 0"] {
+            let err = parse(&format!("SET sessions s1 token {zero}")).unwrap_err();
+            assert_eq!(err.kind, ParseErrorKind::InvalidToken);
+            assert_eq!(err.token, Some(5));
+        }
+        assert_eq!(
+            parse("expire sessions s1 30"),
+            Ok(Command::Expire {
+                table: "sessions".to_string(),
+                key: "s1".to_string(),
+                ttl: Duration::from_secs(30),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_counters() {
+        let incr = |table: &str, key: &str, by: i64| Command::Incr {
+            table: table.to_string(),
+            key: key.to_string(),
+            by,
+        };
+        assert_eq!(parse("INCR stats hits"), Ok(incr("stats", "hits", 1)));
+        assert_eq!(parse("decr stats hits"), Ok(incr("stats", "hits", -1)));
+        assert_eq!(parse("INCRBY stats hits -5"), Ok(incr("stats", "hits", -5)));
+        assert_eq!(parse("DECRBY stats hits 5"), Ok(incr("stats", "hits", -5)));
+        assert_eq!(
+            parse("INCRBYFLOAT stats load 0.25"),
+            Ok(Command::IncrFloat {
+                table: "stats".to_string(),
+                key: "load".to_string(),
+                by: 0.25,
+            })
+        );
+        assert!(matches!(
+            parse("INCRBY stats hits 1.5"),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidCommand,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("INCRBYFLOAT stats load nan"),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidCommand,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("INCR stats"),
+            Err(ParseError {
+                kind: ParseErrorKind::WrongNumberOfArguments,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_parse_conditional_writes() {
+        let condition_of = |input: &str| match parse(input) {
+            Ok(Command::Put { condition, .. }) => condition,
+            other => panic!("Expected Command::Put from {}, got {:?}", input, other),
+        };
+        assert_eq!(condition_of("SET t k v nx"), PutCondition::IfAbsent);
+        assert_eq!(condition_of("SET t k v EX 10 XX"), PutCondition::IfPresent);
+        assert_eq!(condition_of("SETNX t k v"), PutCondition::IfAbsent);
+        assert_eq!(
+            parse("CAS t k 1 2"),
+            Ok(Command::Put {
+                table: "t".to_string(),
+                key: "k".to_string(),
+                val: Value::Int(2),
+                ttl: None,
+                condition: PutCondition::IfEquals(Value::Int(1)),
+            })
+        );
+        assert!(matches!(
+            parse("SET t k v NX XX"),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidCommand,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("CAS t k 1"),
+            Err(ParseError {
+                kind: ParseErrorKind::WrongNumberOfArguments,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_parse_batches() {
+        assert_eq!(
+            parse("MSET t a 1 b 'two'"),
+            Ok(Command::MSet {
+                table: "t".to_string(),
+                pairs: vec![
+                    ("a".to_string(), Value::Int(1)),
+                    ("b".to_string(), Value::from("two")),
+                ],
+            })
+        );
+        assert_eq!(
+            parse("mget t a b"),
+            Ok(Command::MGet {
+                table: "t".to_string(),
+                keys: vec!["a".to_string(), "b".to_string()],
+            })
+        );
+        assert!(matches!(
+            parse("MSET t a 1 b"),
+            Err(ParseError {
+                kind: ParseErrorKind::WrongNumberOfArguments,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("MDEL t"),
+            Err(ParseError {
+                kind: ParseErrorKind::WrongNumberOfArguments,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        assert_eq!(
+            parse("KEYS users user:* LIMIT 10 AFTER user:5"),
+            Ok(Command::Keys {
+                table: "users".to_string(),
+                pattern: Some(KeyPattern::parse("user:*").unwrap()),
+                limit: Some(10),
+                after: Some("user:5".to_string()),
+            })
+        );
+        assert_eq!(
+            parse("keys users limit 10"),
+            Ok(Command::Keys {
+                table: "users".to_string(),
+                pattern: None,
+                limit: Some(10),
+                after: None,
+            })
+        );
+        assert_eq!(
+            parse(r"COUNT users '/^user:\d+$/'"),
+            Ok(Command::Count {
+                table: "users".to_string(),
+                pattern: Some(KeyPattern::parse(r"/^user:\d+$/").unwrap()),
+            })
+        );
+        assert!(matches!(
+            parse("KEYS users /(/"),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidToken,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("COUNT users a b"),
+            Err(ParseError {
+                kind: ParseErrorKind::WrongNumberOfArguments,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_parse_scan_cursor() {
+        assert_eq!(
+            parse("SCAN users 0 MATCH user:* COUNT 5"),
+            Ok(Command::ScanCursor {
+                table: "users".to_string(),
+                cursor: Cursor::start(),
+                pattern: Some(KeyPattern::parse("user:*").unwrap()),
+                count: Some(5),
+            })
+        );
+        let cursor = Cursor::parse("161").unwrap();
+        assert_eq!(
+            parse("SCAN users 161"),
+            Ok(Command::ScanCursor {
+                table: "users".to_string(),
+                cursor,
+                pattern: None,
+                count: None,
+            })
+        );
+        assert!(matches!(
+            parse("SCAN users 0 LIMIT 5"),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidCommand,
+                ..
+            })
+        ));
+        //the range form still works
+        assert!(matches!(
+            parse("SCAN users LIMIT 5"),
+            Ok(Command::Scan { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_table_management() {
+        assert_eq!(
+            parse("rename rates rates_old"),
+            Ok(Command::RenameTable {
+                from: "rates".to_string(),
+                to: "rates_old".to_string(),
+            })
+        );
+        assert_eq!(
+            parse("TRUNCATE rates"),
+            Ok(Command::TruncateTable {
+                table_name: "rates".to_string(),
+            })
+        );
+        assert!(matches!(
+            parse("COPY rates"),
+            Err(ParseError {
+                kind: ParseErrorKind::WrongNumberOfArguments,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_parse_info() {
+        assert_eq!(parse("info"), Ok(Command::Info));
+        assert!(parse("INFO all").is_err());
+        assert_eq!(parse("checkpoint"), Ok(Command::Checkpoint));
+        assert_eq!(parse("COMPACT"), Ok(Command::Compact));
+        assert!(parse("COMPACT now").is_err());
+        assert_eq!(parse("CAS t k 1 2").unwrap().name(), "CAS");
+        assert_eq!(parse("SETNX t k 1").unwrap().name(), "SET");
+    }
+
+    #[test]
+    fn test_parse_alter() {
+        assert_eq!(
+            parse("ALTER sessions TTL 60 DESCRIPTION \"login sessions\""),
+            Ok(Command::AlterTable {
+                table_name: "sessions".to_string(),
+                description: Some(Some("login sessions".to_string())),
+                default_ttl: Some(Some(Duration::from_secs(60))),
+            })
+        );
+        assert_eq!(
+            parse("alter sessions description none"),
+            Ok(Command::AlterTable {
+                table_name: "sessions".to_string(),
+                description: Some(None),
+                default_ttl: None,
+            })
+        );
+        assert!(matches!(
+            parse("ALTER sessions TTL soon"),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidCommand,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("ALTER sessions"),
+            Err(ParseError {
+                kind: ParseErrorKind::WrongNumberOfArguments,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_parse_error_spans() {
+        let err = parse("SCAN events LIMIT ten").unwrap_err();
+        assert_eq!(err.token, Some(3));
+        assert_eq!(err.span, Some(18..21));
+        //a missing argument points just past the end
+        let err = parse("GET users").unwrap_err();
+        assert_eq!((err.token, err.span), (Some(2), Some(9..9)));
+        //an extra one points at itself
+        assert_eq!(parse("LIST  all").unwrap_err().span, Some(6..9));
+        assert_eq!(parse("FETCH t k").unwrap_err().span, Some(0..5));
+        assert_eq!(parse("SET t k v EX 10 XY").unwrap_err().span, Some(16..18));
+        assert_eq!(parse("SET t k [1,").unwrap_err().span, Some(8..11));
+        assert_eq!(parse("SCAN t 0 COUNT x").unwrap_err().span, Some(15..16));
+        //tokens without a line have no span
+        let tokens = vec![Token::Bare("GET".to_string())];
+        let err = parse_tokens(&tokens).unwrap_err();
+        assert_eq!((err.token, err.span), (Some(1), None));
+    }
 }
@@ -0,0 +1,81 @@
+use std::fmt::Display;
+
+use crate::{err_types::ParseError, tokenizer::decode_hex};
+
+///Where an incremental SCAN carries on from.
+///
+///Cursors hold the last key a page looked at rather than a position, so writes between
+///calls can't make a scan skip or repeat a key that exists for the whole scan.
+///They print as `0` for the start (and the end), otherwise `1` followed by the key in hex.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Cursor {
+    after: Option<String>,
+}
+
+impl Cursor {
+    pub fn start() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn after(key: &str) -> Self {
+        Self {
+            after: Some(key.to_string()),
+        }
+    }
+
+    ///The last key already returned, `None` at the start
+    pub fn last_key(&self) -> Option<&str> {
+        self.after.as_deref()
+    }
+
+    ///A cursor handed back at the end of a scan is the start cursor again
+    pub fn is_start(&self) -> bool {
+        self.after.is_none()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let invalid = || ParseError::invalid_token(format!("Invalid cursor: {}", text));
+        if text == "0" {
+            return Ok(Self::start());
+        }
+        let hex = text.strip_prefix('1').ok_or_else(invalid)?;
+        let key =
+            String::from_utf8(decode_hex(hex).map_err(|_| invalid())?).map_err(|_| invalid())?;
+        Ok(Self::after(&key))
+    }
+}
+
+impl Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.after {
+            None => write!(f, "0"),
+            Some(key) => {
+                write!(f, "1")?;
+                for byte in key.as_bytes() {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        for cursor in [
+            Cursor::start(),
+            Cursor::after(""),
+            Cursor::after("user:ü 1"),
+        ] {
+            assert_eq!(Cursor::parse(&cursor.to_string()), Ok(cursor));
+        }
+        assert_eq!(Cursor::after("ab").to_string(), "16162");
+        assert!(Cursor::parse("FROM").is_err());
+        assert!(Cursor::parse("1zz").is_err());
+        assert!(Cursor::parse("1ff").is_err());
+    }
+}
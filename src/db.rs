@@ -1,283 +1,1929 @@
 use std::{
-    collections::HashMap,
-    fs::{self, OpenOptions},
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fs::{self, File, OpenOptions},
     io::Write as IoWrite,
+    ops::{Bound, RangeBounds},
     path::Path,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-use bincode::{Decode, Encode, config, decode_from_slice, encode_to_vec};
+use bincode::{Decode, Encode, config, encode_to_vec};
 
-use crate::{command::Command, err_types::RustyDbErr, wal::WalEntry};
-type Result<T> = std::result::Result<T, RustyDbErr>;
+use crate::{
+    Result,
+    checkpoint::CheckpointPolicy,
+    command::{Command, PutCondition},
+    cursor::Cursor,
+    durability::Durability,
+    err_types::RustyDbErr,
+    expiry::{deadline, now_millis, remaining},
+    pattern::KeyPattern,
+    query_result::QueryResult,
+    sql::{self, Condition, Projection, Statement},
+    stats::{CHECKPOINT_HISTORY_LEN, CheckpointInfo, Stats},
+    table_meta::{TableInfo, TableMeta, TableOptions},
+    value::Value,
+    wal::{
+        WAL_HEADER_LEN, WAL_VERSION, WalEntry, WalReplay, decode_records, encode_record, wal_header,
+    },
+};
+
+///Default number of WAL entries written before we checkpoint
+pub const DEFAULT_CHECKPOINT_THRESHOLD: usize = 1000;
+
+///KEYS lists at most this many keys unless given a LIMIT
+pub const DEFAULT_KEYS_LIMIT: usize = 100;
+
+///Keys a SCAN page looks at unless given a COUNT
+pub const DEFAULT_SCAN_COUNT: usize = 10;
+
+///A table's keys and values, kept sorted by key
+pub type Table = BTreeMap<String, Value>;
+
+///Per table, the unix millis each expiring key expires at
+type Expiries = BTreeMap<String, BTreeMap<String, u64>>;
+
+///Snapshot files start with the magic bytes followed by a little endian format version
+const SNAPSHOT_MAGIC: &[u8; 7] = b"RDBSNAP";
+const SNAPSHOT_VERSION: u16 = 1;
+
+///Everything a snapshot file holds
+#[derive(Encode, Decode)]
+struct Snapshot {
+    tables: BTreeMap<String, Table>,
+    expiries: Expiries,
+    meta: BTreeMap<String, TableMeta>,
+    ///WALs up to this generation are already applied, `None` if no WAL is.
+    ///Replaying them again would redo renames and copies over their own results.
+    wal_generation: Option<u64>,
+}
+
+///Borrowed twin of [`Snapshot`] so saving doesn't clone every table
+#[derive(Encode)]
+struct SnapshotRef<'a> {
+    tables: &'a BTreeMap<String, Table>,
+    expiries: &'a Expiries,
+    meta: &'a BTreeMap<String, TableMeta>,
+    wal_generation: Option<u64>,
+}
+
+///One page of an incremental scan
+#[derive(Debug, PartialEq)]
+pub struct ScanPage<'a> {
+    ///pass this to the next call, it is the start cursor again once the scan is done
+    pub cursor: Cursor,
+    pub entries: Vec<(&'a String, &'a Value)>,
+}
 
-#[derive(Debug, Encode, Decode)]
+#[derive(Debug)]
 pub struct RustyDb {
-    // data: HashMap<String, String>,
-    pub tables: HashMap<String, HashMap<String, String>>,
+    tables: BTreeMap<String, Table>,
+    ///keys with a TTL, expired keys are hidden at once and removed by [`RustyDb::sweep_expired`]
+    expiries: Expiries,
+    ///per table creation and modification times and options
+    meta: BTreeMap<String, TableMeta>,
     ///DB location on the filesyystem
-    pub file_path: String,
+    file_path: String,
     ///write ahead log path
-    pub wal_path: String,
-    pub operations_since_checkpoint: usize,
+    wal_path: String,
+    operations_since_checkpoint: usize,
+    checkpoint_policy: CheckpointPolicy,
+    durability: Durability,
+    ///WAL handle, opened on the first write and kept open after that
+    wal_file: Option<File>,
+    ///size of the WAL file in bytes
+    wal_len: u64,
+    ///generation of the WAL file, the snapshot holds every generation before it
+    wal_generation: u64,
+    last_checkpoint: Instant,
+    ///snapshot being written by a background checkpoint
+    background_checkpoint: Option<JoinHandle<Result<CheckpointInfo>>>,
+    ///why the last background or policy triggered checkpoint failed, cleared by the next
+    ///one that works
+    last_checkpoint_error: Option<String>,
+    ///records written since the last fsync of the WAL
+    unsynced_entries: usize,
+    last_wal_sync: Instant,
+    ///open transaction, if BEGIN has been called
+    transaction: Option<Transaction>,
+    ///outcome of replaying the WAL when the database was opened
+    last_replay: Option<WalReplay>,
+    opened_at: Instant,
+    total_checkpoints: usize,
+    ///the last few checkpoints, oldest first
+    checkpoints: VecDeque<CheckpointInfo>,
+    ///times each command has been run
+    command_counts: BTreeMap<String, u64>,
+    ///checkpoint step to fail at, so the tests can simulate crashes
+    #[cfg(test)]
+    crash_before: Option<CheckpointStep>,
 }
 
-impl RustyDb {
-    pub fn new(file_path: &str) -> Result<Self> {
-        let mut wal_path = format!("{}.wal", file_path);
-        let mut rusty_db = Self {
-            // data: HashMap::new(),
-            tables: HashMap::new(),
+impl Drop for RustyDb {
+    fn drop(&mut self) {
+        //group commit may be holding back records, OsBuffered leaves them to the OS
+        if self.durability != Durability::OsBuffered {
+            self.sync_wal().ok();
+        }
+        if let Some(checkpoint) = self.background_checkpoint.take() {
+            checkpoint.join().ok();
+        }
+    }
+}
+
+///Steps of a checkpoint, in the order they happen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CheckpointStep {
+    WriteSnapshot,
+    SyncSnapshot,
+    RenameSnapshot,
+    SyncDir,
+    TruncateWal,
+}
+
+///Writes made since BEGIN. They are applied in memory straight away so reads see
+///them, but only reach the WAL as a single record on COMMIT.
+#[derive(Debug, Default)]
+struct Transaction {
+    entries: Vec<WalEntry>,
+    ///per entry, the entries that put the in-memory state back the way it was
+    undo: Vec<Vec<WalEntry>>,
+    ///table metadata as it was at BEGIN
+    meta: BTreeMap<String, TableMeta>,
+}
+
+///Builder for opening a [`RustyDb`] with non-default options
+#[derive(Debug, Clone)]
+pub struct RustyDbBuilder {
+    file_path: String,
+    wal_path: Option<String>,
+    checkpoint_policy: CheckpointPolicy,
+    durability: Durability,
+}
+
+impl RustyDbBuilder {
+    pub fn new(file_path: &str) -> Self {
+        Self {
             file_path: file_path.to_string(),
-            wal_path: wal_path.clone(),
+            wal_path: None,
+            checkpoint_policy: CheckpointPolicy::default(),
+            durability: Durability::default(),
+        }
+    }
+
+    ///Where to keep the write ahead log, defaults to `<file_path>.wal`
+    pub fn wal_path(mut self, wal_path: &str) -> Self {
+        self.wal_path = Some(wal_path.to_string());
+        self
+    }
+
+    ///How many WAL entries to write before checkpointing
+    pub fn checkpoint_threshold(mut self, threshold: usize) -> Self {
+        self.checkpoint_policy.max_entries = Some(threshold);
+        self
+    }
+
+    ///When to checkpoint, defaults to after [`DEFAULT_CHECKPOINT_THRESHOLD`] WAL entries
+    pub fn checkpoint_policy(mut self, policy: CheckpointPolicy) -> Self {
+        self.checkpoint_policy = policy;
+        self
+    }
+
+    ///When WAL writes are fsynced, defaults to [`Durability::Always`]
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    ///Open the database, loading the snapshot and replaying the WAL if present
+    pub fn open(self) -> Result<RustyDb> {
+        let wal_path = self
+            .wal_path
+            .unwrap_or_else(|| format!("{}.wal", self.file_path));
+        let mut rusty_db = RustyDb {
+            tables: BTreeMap::new(),
+            expiries: BTreeMap::new(),
+            meta: BTreeMap::new(),
+            file_path: self.file_path,
+            wal_path,
             operations_since_checkpoint: 0,
+            checkpoint_policy: self.checkpoint_policy,
+            durability: self.durability,
+            wal_file: None,
+            wal_len: 0,
+            wal_generation: 0,
+            last_checkpoint: Instant::now(),
+            background_checkpoint: None,
+            last_checkpoint_error: None,
+            unsynced_entries: 0,
+            last_wal_sync: Instant::now(),
+            transaction: None,
+            last_replay: None,
+            opened_at: Instant::now(),
+            total_checkpoints: 0,
+            checkpoints: VecDeque::new(),
+            command_counts: BTreeMap::new(),
+            #[cfg(test)]
+            crash_before: None,
         };
 
-        if Path::new(file_path).exists() {
+        //a checkpoint died before renaming its snapshot into place, the WAL still has its data
+        fs::remove_file(rusty_db.snapshot_tmp_path()).ok();
+
+        if Path::new(&rusty_db.file_path).exists() {
             rusty_db.load_from_disk()?;
         }
 
-        if Path::new(&wal_path).exists() {
+        let interrupted = Path::new(&rusty_db.old_wal_path()).exists();
+        if interrupted || Path::new(&rusty_db.wal_path).exists() {
             rusty_db.replay_wal()?;
+            //new records can't be appended to a WAL from before the header, start a fresh one
+            let outdated = rusty_db
+                .last_replay
+                .as_ref()
+                .is_some_and(|replay| replay.generation.is_some() && replay.version != WAL_VERSION);
+            //nor to one the snapshot already holds, it would be skipped next time too
+            let covered = rusty_db.last_replay.as_ref().is_some_and(|replay| {
+                replay
+                    .generation
+                    .is_some_and(|generation| generation < rusty_db.wal_generation)
+            });
+            //and a background checkpoint that never finished is done over
+            if outdated || covered || interrupted {
+                rusty_db.checkpoint()?;
+            }
         }
+        rusty_db.wal_len = fs::metadata(&rusty_db.wal_path).map_or(0, |meta| meta.len());
 
         Ok(rusty_db)
     }
+}
+
+impl RustyDb {
+    ///Open the database at `file_path` with default options
+    pub fn new(file_path: &str) -> Result<Self> {
+        RustyDbBuilder::new(file_path).open()
+    }
+
+    pub fn builder(file_path: &str) -> RustyDbBuilder {
+        RustyDbBuilder::new(file_path)
+    }
+
+    ///DB location on the filesystem
+    pub fn file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    ///Write ahead log location on the filesystem
+    pub fn wal_path(&self) -> &str {
+        &self.wal_path
+    }
+
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    ///fsync any WAL records not yet on disk, whatever the durability setting
+    pub fn sync_wal(&mut self) -> Result<()> {
+        if let Some(file) = &self.wal_file
+            && self.unsynced_entries > 0
+        {
+            file.sync_data()
+                .map_err(|e| RustyDbErr::io(format!("syncing WAL {}", self.wal_path), e))?;
+        }
+        self.unsynced_entries = 0;
+        self.last_wal_sync = Instant::now();
+        Ok(())
+    }
+
+    ///fsync pending WAL records once group commit's interval has passed since the last
+    ///sync, returns whether it did. Writes only check the interval as they happen, call
+    ///this now and then so the last records before a quiet spell don't wait for the next.
+    pub fn sync_wal_if_due(&mut self) -> Result<bool> {
+        let Durability::GroupCommit { interval, .. } = self.durability else {
+            return Ok(false);
+        };
+        if self.unsynced_entries == 0 || self.last_wal_sync.elapsed() < interval {
+            return Ok(false);
+        }
+        self.sync_wal()?;
+        Ok(true)
+    }
+
+    ///WAL records written but not yet fsynced
+    pub fn unsynced_wal_entries(&self) -> usize {
+        self.unsynced_entries
+    }
+
+    ///Number of WAL entries written since the last checkpoint
+    pub fn operations_since_checkpoint(&self) -> usize {
+        self.operations_since_checkpoint
+    }
+
+    pub fn checkpoint_policy(&self) -> CheckpointPolicy {
+        self.checkpoint_policy
+    }
+
+    pub fn set_checkpoint_policy(&mut self, policy: CheckpointPolicy) {
+        self.checkpoint_policy = policy;
+    }
 
+    ///Append an entry to the WAL. Doesn't checkpoint, see [`RustyDb::checkpoint_if_due`].
     pub fn write_wal(&mut self, entry: &WalEntry) -> Result<()> {
-        let config = config::standard();
-        let encoded = encode_to_vec(entry, config)
-            .map_err(|e| RustyDbErr::SerializationError(e.to_string()))?;
-        //write length prefix of 4 so we know where each entry ends
-        let len = encoded.len() as u32;
-        let len_bytes = len.to_le_bytes();
-        //open or create wal file
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.wal_path)
-            .map_err(|e| RustyDbErr::SerializationError(e.to_string()))?;
-
-        file.write_all(&len_bytes)
-            .map_err(|e| RustyDbErr::IoError(e.to_string()))?;
-        file.write_all(&encoded)
-            .map_err(|e| RustyDbErr::IoError(e.to_string()))?;
-
-        file.flush()
-            .map_err(|e| RustyDbErr::IoError(e.to_string()))?;
+        self.write_wal_at(entry, now_millis())
+    }
 
-        self.operations_since_checkpoint += 1;
+    ///Append an entry written at `at` unix millis, the time replay will touch its tables with
+    fn write_wal_at(&mut self, entry: &WalEntry, at: u64) -> Result<()> {
+        let record = encode_record(entry, at)?;
+        let file = match self.wal_file.take() {
+            Some(file) => file,
+            None => {
+                let file = open_wal(&self.wal_path, self.wal_generation)?;
+                self.wal_len = file
+                    .metadata()
+                    .map_err(|e| RustyDbErr::io(format!("reading WAL {}", self.wal_path), e))?
+                    .len();
+                file
+            }
+        };
+        self.wal_file
+            .insert(file)
+            .write_all(&record)
+            .map_err(|e| RustyDbErr::io(format!("writing WAL {}", self.wal_path), e))?;
+        self.wal_len += record.len() as u64;
+        self.unsynced_entries += 1;
 
-        if self.operations_since_checkpoint > 1000 {
-            self.checkpoint()?;
-            self.operations_since_checkpoint = 0;
+        let sync_due = match self.durability {
+            Durability::Always => true,
+            Durability::GroupCommit {
+                interval,
+                max_entries,
+            } => self.unsynced_entries >= max_entries || self.last_wal_sync.elapsed() >= interval,
+            Durability::OsBuffered => false,
+        };
+        if sync_due {
+            self.sync_wal()?;
         }
 
+        self.operations_since_checkpoint += 1;
         Ok(())
     }
 
-    pub fn execute(&mut self, cmd: Command) -> Result<String> {
+    pub fn execute(&mut self, cmd: Command) -> Result<QueryResult> {
+        self.record_command(cmd.name());
         match cmd {
-            Command::Get { table, key } => {
-                let val = self.get(&table, &key)?;
-                Ok(format!("{}", val))
+            Command::Get { table, key } => Ok(QueryResult::Value(self.get(&table, &key)?.clone())),
+            Command::Put {
+                table,
+                key,
+                val,
+                ttl,
+                condition,
+            } => {
+                if self.put_if(table, key, val, &condition, ttl)? {
+                    Ok(QueryResult::ack("Ok"))
+                } else {
+                    Ok(QueryResult::NotApplied("Not written".to_string()))
+                }
+            }
+            Command::Incr { table, key, by } => Ok(QueryResult::Value(Value::Int(
+                self.incr_by(&table, &key, by)?,
+            ))),
+            Command::IncrFloat { table, key, by } => Ok(QueryResult::Value(Value::Float(
+                self.incr_by_float(&table, &key, by)?,
+            ))),
+            Command::Expire { table, key, ttl } => {
+                self.expire(&table, &key, ttl)?;
+                Ok(QueryResult::ack("Ok"))
+            }
+            Command::Ttl { table, key } => {
+                let secs = match self.ttl(&table, &key)? {
+                    //round like redis, so a fresh EX 60 reads back as 60
+                    Some(left) => ((left.as_millis() + 500) / 1000) as i64,
+                    None => -1,
+                };
+                Ok(QueryResult::Value(Value::Int(secs)))
+            }
+            Command::Persist { table, key } => {
+                if self.persist(&table, &key)? {
+                    Ok(QueryResult::ack("Ok"))
+                } else {
+                    Ok(QueryResult::NotApplied("No expiry set".to_string()))
+                }
             }
-            Command::Put { table, key, val } => {
-                self.put(table, key, val)?;
-                Ok("Ok".to_string())
+            Command::Del { table, key } => Ok(QueryResult::Value(self.delete(&table, &key)?)),
+            Command::MGet { table, keys } => {
+                let vals = self.mget(&table, &keys)?;
+                let vals = vals.into_iter().map(|val| val.cloned()).collect::<Vec<_>>();
+                Ok(QueryResult::Values(keys.into_iter().zip(vals).collect()))
             }
-            Command::Del { table, key } => {
-                let val = self.delete(&table, &key)?;
-                Ok(format!("{}", val))
+            Command::MSet { table, pairs } => {
+                let count = pairs.len();
+                self.mset(&table, pairs)?;
+                Ok(QueryResult::ack(format!("Set {} keys", count)))
             }
+            Command::MDel { table, keys } => Ok(QueryResult::ack(format!(
+                "Deleted {} keys",
+                self.mdel(&table, &keys)?
+            ))),
             Command::CreateTable { table_name } => {
                 self.create_table(&table_name)?;
-                Ok(format!("Created table {}", table_name))
+                Ok(QueryResult::ack(format!("Created table {}", table_name)))
             }
             Command::DropTable { table_name } => {
                 self.drop_table(&table_name)?;
-                Ok(format!("Dropped table {}", table_name))
+                Ok(QueryResult::ack(format!("Dropped table {}", table_name)))
+            }
+            Command::RenameTable { from, to } => {
+                self.rename_table(&from, &to)?;
+                Ok(QueryResult::ack(format!(
+                    "Renamed table {} to {}",
+                    from, to
+                )))
+            }
+            Command::TruncateTable { table_name } => {
+                let count = self.truncate_table(&table_name)?;
+                Ok(QueryResult::ack(format!(
+                    "Truncated table {}, {} keys removed",
+                    table_name, count
+                )))
+            }
+            Command::CopyTable { from, to } => {
+                self.copy_table(&from, &to)?;
+                Ok(QueryResult::ack(format!("Copied table {} to {}", from, to)))
             }
-            Command::ListTables => {
-                let list_tables = self.list_tables();
-                if list_tables.is_empty() {
-                    return Ok("No tables found".to_string());
+            Command::Describe { table_name } => {
+                Ok(QueryResult::Table(self.table_info(&table_name)?))
+            }
+            Command::AlterTable {
+                table_name,
+                description,
+                default_ttl,
+            } => {
+                let mut options = self.table_info(&table_name)?.options;
+                if let Some(description) = description {
+                    options.description = description;
+                }
+                if let Some(default_ttl) = default_ttl {
+                    options.default_ttl = default_ttl;
                 }
-                Ok(list_tables.join("\n"))
+                self.set_table_options(&table_name, options)?;
+                Ok(QueryResult::ack(format!("Altered table {}", table_name)))
+            }
+            Command::Info => Ok(QueryResult::Stats(self.stats()?)),
+            Command::Checkpoint => {
+                let entries = self.operations_since_checkpoint;
+                self.checkpoint()?;
+                Ok(QueryResult::ack(format!(
+                    "Checkpointed {} WAL entries",
+                    entries
+                )))
+            }
+            Command::Compact => {
+                let swept = self.compact()?;
+                Ok(QueryResult::ack(format!(
+                    "Compacted, {} expired keys removed",
+                    swept
+                )))
             }
+            Command::Sql(statement) => self.run_sql(statement),
+            Command::ListTables => Ok(QueryResult::Tables(self.list_tables())),
+            Command::Begin => {
+                self.begin()?;
+                Ok(QueryResult::ack("Ok"))
+            }
+            Command::Commit => {
+                let count = self.commit()?;
+                Ok(QueryResult::ack(format!("Committed {} operations", count)))
+            }
+            Command::Rollback => {
+                let count = self.rollback()?;
+                Ok(QueryResult::ack(format!(
+                    "Rolled back {} operations",
+                    count
+                )))
+            }
+            Command::Scan {
+                table,
+                from,
+                to,
+                limit,
+            } => {
+                let from = from.map_or(Bound::Unbounded, Bound::Included);
+                let to = to.map_or(Bound::Unbounded, Bound::Excluded);
+                let rows = self.range(&table, (from, to))?;
+                Ok(QueryResult::rows(
+                    rows.take(limit.unwrap_or(usize::MAX)),
+                    None,
+                ))
+            }
+            Command::Prefix {
+                table,
+                prefix,
+                limit,
+            } => {
+                let rows = self.prefix(&table, &prefix)?;
+                Ok(QueryResult::rows(
+                    rows.take(limit.unwrap_or(usize::MAX)),
+                    None,
+                ))
+            }
+            Command::ScanCursor {
+                table,
+                cursor,
+                pattern,
+                count,
+            } => {
+                let page = self.scan(
+                    &table,
+                    &cursor,
+                    pattern.as_ref(),
+                    count.unwrap_or(DEFAULT_SCAN_COUNT),
+                )?;
+                Ok(QueryResult::rows(
+                    page.entries.into_iter(),
+                    Some(page.cursor),
+                ))
+            }
+            Command::Keys {
+                table,
+                pattern,
+                limit,
+                after,
+            } => {
+                let limit = limit.unwrap_or(DEFAULT_KEYS_LIMIT);
+                //one extra tells us whether there is another page
+                let mut keys: Vec<String> = self
+                    .keys(&table, pattern.as_ref(), after.as_deref())?
+                    .take(limit.saturating_add(1))
+                    .cloned()
+                    .collect();
+                let more = keys.len() > limit;
+                keys.truncate(limit);
+                Ok(QueryResult::Keys { keys, more })
+            }
+            Command::Count { table, pattern } => Ok(QueryResult::Count(
+                self.keys(&table, pattern.as_ref(), None)?.count(),
+            )),
         }
     }
 
-    pub fn get(&self, table: &str, key: &str) -> Result<&String> {
-        self.tables
+    ///Get a value from a table
+    pub fn get(&self, table: &str, key: &str) -> Result<&Value> {
+        let val = self
+            .tables
             .get(table)
             .ok_or_else(|| RustyDbErr::TableNotFound(table.to_string()))?
             .get(key)
-            .ok_or_else(|| RustyDbErr::KeyNotFound(key.to_string()))
+            .ok_or_else(|| RustyDbErr::KeyNotFound(key.to_string()))?;
+        //expired keys linger until swept, but nobody gets to see them
+        if self.is_expired(table, key, now_millis()) {
+            return Err(RustyDbErr::KeyNotFound(key.to_string()));
+        }
+        Ok(val)
+    }
+
+    ///Set a value in a table. Any expiry the key had is replaced by the table's default ttl,
+    ///or cleared if the table has none.
+    pub fn put(&mut self, table: String, key: String, val: impl Into<Value>) -> Result<()> {
+        self.table(&table)?;
+        let mut entries = self.put_entries(table, key, val.into());
+        let entry = match entries.len() {
+            1 => entries.remove(0),
+            _ => WalEntry::Transaction { entries },
+        };
+        self.log_and_apply(entry)
+    }
+
+    ///A put, followed by an expiry when the table gives new keys one
+    fn put_entries(&self, table: String, key: String, val: Value) -> Vec<WalEntry> {
+        let default_ttl = self
+            .meta
+            .get(&table)
+            .and_then(|meta| meta.options.default_ttl);
+        let expire = default_ttl.map(|ttl| WalEntry::Expire {
+            table: table.clone(),
+            key: key.clone(),
+            expires_at: Some(deadline(ttl)),
+        });
+        let mut entries = vec![WalEntry::Put { table, key, val }];
+        entries.extend(expire);
+        entries
     }
 
-    pub fn put(&mut self, table: String, key: String, val: String) -> Result<()> {
-        self.write_wal(&WalEntry::Put {
+    ///Set a value in a table that expires after `ttl`
+    pub fn put_with_ttl(
+        &mut self,
+        table: String,
+        key: String,
+        val: impl Into<Value>,
+        ttl: Duration,
+    ) -> Result<()> {
+        self.table(&table)?;
+        let expire = WalEntry::Expire {
             table: table.clone(),
             key: key.clone(),
+            expires_at: Some(deadline(ttl)),
+        };
+        //one record, so a crash can't leave the value without its expiry
+        self.log_and_apply(WalEntry::Transaction {
+            entries: vec![
+                WalEntry::Put {
+                    table,
+                    key,
+                    val: val.into(),
+                },
+                expire,
+            ],
+        })
+    }
+
+    ///Put only if `condition` holds, optionally expiring after `ttl`.
+    ///Returns whether the write happened.
+    pub fn put_if(
+        &mut self,
+        table: String,
+        key: String,
+        val: impl Into<Value>,
+        condition: &PutCondition,
+        ttl: Option<Duration>,
+    ) -> Result<bool> {
+        let current = match self.get(&table, &key) {
+            Ok(val) => Some(val),
+            Err(RustyDbErr::KeyNotFound(_)) => None,
+            Err(why) => return Err(why),
+        };
+        let allowed = match condition {
+            PutCondition::Always => true,
+            PutCondition::IfAbsent => current.is_none(),
+            PutCondition::IfPresent => current.is_some(),
+            PutCondition::IfEquals(expected) => current == Some(expected),
+        };
+        if !allowed {
+            return Ok(false);
+        }
+        match ttl {
+            Some(ttl) => self.put_with_ttl(table, key, val, ttl)?,
+            None => self.put(table, key, val)?,
+        }
+        Ok(true)
+    }
+
+    ///Add `delta` to an integer value, a missing key counts as 0. Returns the new value.
+    pub fn incr_by(&mut self, table: &str, key: &str, delta: i64) -> Result<i64> {
+        let updated = self.update_number(table, key, |current| {
+            let current = match current {
+                None => 0,
+                Some(Value::Int(i)) => *i,
+                Some(Value::Str(s)) => s.trim().parse::<i64>().map_err(|_| not_integer(key))?,
+                Some(_) => return Err(not_integer(key)),
+            };
+            current.checked_add(delta).map(Value::Int).ok_or_else(|| {
+                RustyDbErr::InvalidQuery(format!("Increment would overflow {}", key))
+            })
+        })?;
+        match updated {
+            Value::Int(i) => Ok(i),
+            _ => unreachable!("incr_by always stores an int"),
+        }
+    }
+
+    ///Add `delta` to a numeric value, a missing key counts as 0. Returns the new value.
+    pub fn incr_by_float(&mut self, table: &str, key: &str, delta: f64) -> Result<f64> {
+        let updated = self.update_number(table, key, |current| {
+            let current = match current {
+                None => 0.0,
+                Some(Value::Int(i)) => *i as f64,
+                Some(Value::Float(x)) => *x,
+                Some(Value::Str(s)) => s.trim().parse::<f64>().map_err(|_| not_float(key))?,
+                Some(_) => return Err(not_float(key)),
+            };
+            let result = current + delta;
+            if !result.is_finite() {
+                return Err(RustyDbErr::InvalidQuery(format!(
+                    "Increment would make {} NaN or infinite",
+                    key
+                )));
+            }
+            Ok(Value::Float(result))
+        })?;
+        match updated {
+            Value::Float(x) => Ok(x),
+            _ => unreachable!("incr_by_float always stores a float"),
+        }
+    }
+
+    ///Read-modify-write a key as one logged put, so replay just sees the result.
+    ///Unlike a plain put the key keeps its expiry.
+    fn update_number(
+        &mut self,
+        table: &str,
+        key: &str,
+        update: impl FnOnce(Option<&Value>) -> Result<Value>,
+    ) -> Result<Value> {
+        let current = match self.get(table, key) {
+            Ok(val) => Some(val),
+            Err(RustyDbErr::KeyNotFound(_)) => None,
+            Err(why) => return Err(why),
+        };
+        let expires_at = match current {
+            Some(_) => self.expiry_of(table, key),
+            None => self
+                .meta
+                .get(table)
+                .and_then(|meta| meta.options.default_ttl)
+                .map(deadline),
+        };
+        let val = update(current)?;
+        let put = WalEntry::Put {
+            table: table.to_string(),
+            key: key.to_string(),
             val: val.clone(),
+        };
+        match expires_at {
+            Some(expires_at) => self.log_and_apply(WalEntry::Transaction {
+                entries: vec![
+                    put,
+                    WalEntry::Expire {
+                        table: table.to_string(),
+                        key: key.to_string(),
+                        expires_at: Some(expires_at),
+                    },
+                ],
+            })?,
+            None => self.log_and_apply(put)?,
+        }
+        Ok(val)
+    }
+
+    ///Expire an existing key after `ttl`, a zero `ttl` deletes it
+    pub fn expire(&mut self, table: &str, key: &str, ttl: Duration) -> Result<()> {
+        self.get(table, key)?;
+        self.log_and_apply(WalEntry::Expire {
+            table: table.to_string(),
+            key: key.to_string(),
+            expires_at: Some(deadline(ttl)),
+        })
+    }
+
+    ///Time left before a key expires, `None` if it never does
+    pub fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>> {
+        self.get(table, key)?;
+        Ok(self.expiry_of(table, key).map(remaining))
+    }
+
+    ///Stop a key from expiring, returns false if it had no expiry
+    pub fn persist(&mut self, table: &str, key: &str) -> Result<bool> {
+        self.get(table, key)?;
+        if self.expiry_of(table, key).is_none() {
+            return Ok(false);
+        }
+        self.log_and_apply(WalEntry::Expire {
+            table: table.to_string(),
+            key: key.to_string(),
+            expires_at: None,
         })?;
-        self.tables
-            .get_mut(&table)
-            .ok_or_else(|| RustyDbErr::TableNotFound(table))?
-            .insert(key, val);
-        // self.save_to_disk()?;
-        Ok(())
+        Ok(true)
+    }
+
+    ///Remove expired keys from memory, returns how many went.
+    ///Not logged: the expiry times are in the WAL already so replay hides them too.
+    pub fn sweep_expired(&mut self) -> usize {
+        let now = now_millis();
+        let mut swept = 0;
+        for (table, expiries) in self.expiries.iter_mut() {
+            let expired: Vec<String> = expiries
+                .iter()
+                .filter(|(_, expires_at)| **expires_at <= now)
+                .map(|(key, _)| key.to_string())
+                .collect();
+            for key in expired {
+                expiries.remove(&key);
+                if let Some(t) = self.tables.get_mut(table) {
+                    t.remove(&key);
+                }
+                swept += 1;
+            }
+        }
+        self.expiries.retain(|_, expiries| !expiries.is_empty());
+        swept
+    }
+
+    fn expiry_of(&self, table: &str, key: &str) -> Option<u64> {
+        self.expiries.get(table)?.get(key).copied()
+    }
+
+    fn is_expired(&self, table: &str, key: &str, now: u64) -> bool {
+        self.expiry_of(table, key)
+            .is_some_and(|expires_at| expires_at <= now)
     }
 
     ///Delete a value from a table
-    pub fn delete(&mut self, table: &str, key: &str) -> Result<String> {
-        self.write_wal(&WalEntry::Delete {
+    pub fn delete(&mut self, table: &str, key: &str) -> Result<Value> {
+        let deleted = self.get(table, key)?.clone();
+        self.log_and_apply(WalEntry::Delete {
             table: table.to_string(),
             key: key.to_string(),
         })?;
-        let deleted = self
-            .tables
-            .get_mut(table)
-            .ok_or_else(|| RustyDbErr::TableNotFound(table.to_string()))?
-            .remove(key)
-            .ok_or_else(|| RustyDbErr::KeyNotFound(key.to_string()))?;
-        // self.save_to_disk()?;
         Ok(deleted)
     }
 
+    ///Get several keys from one table, `None` for keys that don't exist
+    pub fn mget<K: AsRef<str>>(&self, table: &str, keys: &[K]) -> Result<Vec<Option<&Value>>> {
+        self.table(table)?;
+        Ok(keys
+            .iter()
+            .map(|key| self.get(table, key.as_ref()).ok())
+            .collect())
+    }
+
+    ///Set several keys in one table. The batch is one WAL record, so after a crash
+    ///either every key was written or none was.
+    pub fn mset(&mut self, table: &str, pairs: Vec<(String, Value)>) -> Result<()> {
+        self.table(table)?;
+        if pairs.is_empty() {
+            return Ok(());
+        }
+        let entries = pairs
+            .into_iter()
+            .flat_map(|(key, val)| self.put_entries(table.to_string(), key, val))
+            .collect();
+        self.log_and_apply(WalEntry::Transaction { entries })
+    }
+
+    ///Delete several keys from one table as one WAL record, returns how many existed.
+    ///Missing keys are skipped rather than failing the batch.
+    pub fn mdel<K: AsRef<str>>(&mut self, table: &str, keys: &[K]) -> Result<usize> {
+        self.table(table)?;
+        let existing: BTreeSet<&str> = keys
+            .iter()
+            .map(AsRef::as_ref)
+            .filter(|key| self.get(table, key).is_ok())
+            .collect();
+        let count = existing.len();
+        if count == 0 {
+            return Ok(0);
+        }
+        let entries = existing
+            .into_iter()
+            .map(|key| WalEntry::Delete {
+                table: table.to_string(),
+                key: key.to_string(),
+            })
+            .collect();
+        self.log_and_apply(WalEntry::Transaction { entries })?;
+        Ok(count)
+    }
+
+    ///Run a SQL statement against the key/value view of a table, see [`crate::sql`].
+    ///Each write is one WAL record.
+    pub fn run_sql(&mut self, statement: Statement) -> Result<QueryResult> {
+        match statement {
+            Statement::Select {
+                table,
+                projection,
+                filter,
+                limit,
+            } => {
+                let rows = self
+                    .sql_rows(&table, &filter)?
+                    .take(limit.unwrap_or(usize::MAX));
+                Ok(match projection {
+                    Projection::Rows => QueryResult::rows(rows, None),
+                    Projection::Keys => QueryResult::Keys {
+                        keys: rows.map(|(key, _)| key.clone()).collect(),
+                        more: false,
+                    },
+                    Projection::Values => {
+                        QueryResult::Column(rows.map(|(_, val)| val.clone()).collect())
+                    }
+                    Projection::Count => QueryResult::Count(rows.count()),
+                })
+            }
+            Statement::Insert { table, rows } => {
+                self.table(&table)?;
+                let mut keys = BTreeSet::new();
+                for (key, _) in &rows {
+                    if !keys.insert(key) || self.get(&table, key).is_ok() {
+                        return Err(RustyDbErr::InvalidQuery(format!(
+                            "Key already exists: {}",
+                            key
+                        )));
+                    }
+                }
+                let count = rows.len();
+                self.mset(&table, rows)?;
+                Ok(QueryResult::ack(format!("Inserted {} rows", count)))
+            }
+            Statement::Update {
+                table,
+                value,
+                filter,
+            } => {
+                let entries: Vec<WalEntry> = self
+                    .sql_rows(&table, &filter)?
+                    .flat_map(|(key, _)| {
+                        //a put clears the expiry, so set it again
+                        let expires_at = self.expiry_of(&table, key);
+                        let expire = expires_at.map(|expires_at| WalEntry::Expire {
+                            table: table.clone(),
+                            key: key.clone(),
+                            expires_at: Some(expires_at),
+                        });
+                        let put = WalEntry::Put {
+                            table: table.clone(),
+                            key: key.clone(),
+                            val: value.clone(),
+                        };
+                        std::iter::once(put).chain(expire)
+                    })
+                    .collect();
+                let count = entries
+                    .iter()
+                    .filter(|entry| matches!(entry, WalEntry::Put { .. }))
+                    .count();
+                if count > 0 {
+                    self.log_and_apply(WalEntry::Transaction { entries })?;
+                }
+                Ok(QueryResult::ack(format!("Updated {} rows", count)))
+            }
+            Statement::Delete { table, filter } => {
+                let keys: Vec<String> = self
+                    .sql_rows(&table, &filter)?
+                    .map(|(key, _)| key.clone())
+                    .collect();
+                Ok(QueryResult::ack(format!(
+                    "Deleted {} rows",
+                    self.mdel(&table, &keys)?
+                )))
+            }
+        }
+    }
+
+    ///The rows of a table a WHERE clause picks, in key order
+    fn sql_rows<'a>(
+        &'a self,
+        table: &str,
+        filter: &'a [Condition],
+    ) -> Result<impl Iterator<Item = (&'a String, &'a Value)> + 'a> {
+        Ok(self
+            .range(table, sql::key_range(filter))?
+            .filter(|(key, val)| filter.iter().all(|cond| cond.matches(key, val))))
+    }
+
     ///Create a table
     pub fn create_table(&mut self, table: &str) -> Result<()> {
-        self.write_wal(&WalEntry::CreateTable {
-            table: table.to_string(),
-        })?;
         if self.tables.contains_key(table) {
             return Err(RustyDbErr::TableExists(table.to_string()));
         }
-        self.tables.insert(table.to_string(), HashMap::new());
-        // self.save_to_disk()?;
-        Ok(())
+        self.log_and_apply(WalEntry::CreateTable {
+            table: table.to_string(),
+        })
     }
 
     ///Drop a table
     pub fn drop_table(&mut self, table: &str) -> Result<()> {
-        self.write_wal(&WalEntry::DropTable {
+        self.table(table)?;
+        self.log_and_apply(WalEntry::DropTable {
             table: table.to_string(),
-        })?;
-        if self.tables.contains_key(table) {
-            return Err(RustyDbErr::TableNotFound(table.to_string()));
+        })
+    }
+
+    ///Rename a table, `to` must not exist yet
+    pub fn rename_table(&mut self, from: &str, to: &str) -> Result<()> {
+        self.table(from)?;
+        if self.tables.contains_key(to) {
+            return Err(RustyDbErr::TableExists(to.to_string()));
         }
-        // self.tables.remove(table);
-        self.save_to_disk()?;
-        Ok(())
+        self.log_and_apply(WalEntry::RenameTable {
+            from: from.to_string(),
+            to: to.to_string(),
+        })
     }
 
-    ///List all the tables
-    pub fn list_tables(&self) -> Vec<String> {
-        self.tables.keys().map(|key| key.to_string()).collect()
+    ///Delete every key in a table, returns how many there were
+    pub fn truncate_table(&mut self, table: &str) -> Result<usize> {
+        let count = self.keys(table, None, None)?.count();
+        self.log_and_apply(WalEntry::TruncateTable {
+            table: table.to_string(),
+        })?;
+        Ok(count)
     }
 
-    pub fn save_to_disk(&mut self) -> Result<()> {
-        let config = config::standard();
-        let encoded = encode_to_vec(&self.tables, config)
-            .map_err(|e| RustyDbErr::SerializationError(e.to_string()))?;
+    ///Copy a table and its expiries into a new table `to`.
+    ///The copy is one WAL record however big the table is.
+    pub fn copy_table(&mut self, from: &str, to: &str) -> Result<()> {
+        self.table(from)?;
+        if self.tables.contains_key(to) {
+            return Err(RustyDbErr::TableExists(to.to_string()));
+        }
+        self.log_and_apply(WalEntry::CopyTable {
+            from: from.to_string(),
+            to: to.to_string(),
+        })
+    }
 
-        fs::write(&self.file_path, encoded).map_err(|e| RustyDbErr::IoError(e.to_string()))?;
+    ///Replace a table's description and default ttl
+    pub fn set_table_options(&mut self, table: &str, options: TableOptions) -> Result<()> {
+        self.table(table)?;
+        self.log_and_apply(WalEntry::SetTableOptions {
+            table: table.to_string(),
+            options,
+        })
+    }
 
-        Ok(())
+    ///Metadata for a table, with its key count and size worked out from its contents
+    pub fn table_info(&self, table: &str) -> Result<TableInfo> {
+        let meta = self.meta.get(table).cloned().unwrap_or_default();
+        let mut info = TableInfo {
+            name: table.to_string(),
+            created_at: meta.created_at,
+            modified_at: meta.modified_at,
+            keys: 0,
+            approx_bytes: 0,
+            options: meta.options,
+        };
+        for (key, val) in self.range::<&str>(table, ..)? {
+            info.keys += 1;
+            info.approx_bytes += key.len() + val.approx_size();
+        }
+        Ok(info)
     }
 
-    pub fn load_from_disk(&mut self) -> Result<()> {
-        let config = config::standard();
-        let data = fs::read(&self.file_path).map_err(|e| RustyDbErr::IoError(e.to_string()))?;
-        let (decoded, _len): (HashMap<String, HashMap<String, String>>, usize) =
-            bincode::decode_from_slice(&data, config)
-                .map_err(|e| RustyDbErr::SerializationError(e.to_string()))?;
+    ///Count a run of `command` towards [`Stats::commands`]. [`RustyDb::execute`] does this
+    ///itself, it's for front ends that call the other methods directly.
+    pub fn record_command(&mut self, command: &str) {
+        *self.command_counts.entry(command.to_string()).or_default() += 1;
+    }
+
+    ///Sizes, checkpoint history and command counts for the whole database
+    pub fn stats(&self) -> Result<Stats> {
+        let file_len = |path: &str| fs::metadata(path).map_or(0, |meta| meta.len());
+        let mut keys = 0;
+        for table in self.tables.keys() {
+            keys += self.keys(table, None, None)?.count();
+        }
+        Ok(Stats {
+            tables: self.tables.len(),
+            keys,
+            expiring_keys: self.expiries.values().map(BTreeMap::len).sum(),
+            snapshot_bytes: file_len(&self.file_path),
+            wal_bytes: file_len(&self.wal_path),
+            operations_since_checkpoint: self.operations_since_checkpoint,
+            unsynced_wal_entries: self.unsynced_entries,
+            total_checkpoints: self.total_checkpoints,
+            checkpoints: self.checkpoints.iter().cloned().collect(),
+            last_checkpoint_error: self.last_checkpoint_error.clone(),
+            uptime: self.opened_at.elapsed(),
+            commands: self.command_counts.clone(),
+        })
+    }
 
-        self.tables = decoded;
+    ///Start a transaction, writes are held back from the WAL until [`RustyDb::commit`]
+    pub fn begin(&mut self) -> Result<()> {
+        if self.transaction.is_some() {
+            return Err(RustyDbErr::TransactionError(
+                "Transaction already in progress".to_string(),
+            ));
+        }
+        self.transaction = Some(Transaction {
+            meta: self.meta.clone(),
+            ..Transaction::default()
+        });
         Ok(())
     }
 
-    ///Replay the wal to reconstruct data
-    pub fn replay_wal(&mut self) -> Result<()> {
-        let data = fs::read(&self.wal_path).map_err(|e| RustyDbErr::IoError(e.to_string()))?;
+    ///Write the open transaction to the WAL as one record, returns the number of writes
+    pub fn commit(&mut self) -> Result<usize> {
+        let transaction = self.transaction.take().ok_or_else(no_transaction)?;
+        let count = transaction.entries.len();
+        if count > 0 {
+            let entry = WalEntry::Transaction {
+                entries: transaction.entries,
+            };
+            let at = now_millis();
+            if let Err(why) = self.write_wal_at(&entry, at) {
+                //nothing reached the WAL, so memory must not keep the writes either
+                self.undo(Transaction {
+                    entries: Vec::new(),
+                    ..transaction
+                })?;
+                return Err(why);
+            }
+            //replay will see the commit time, not when each write was made
+            self.touch(&entry, at);
+            //the commit is in the WAL, a failed checkpoint is kept for stats and retried later
+            self.checkpoint_if_due().ok();
+        }
+        Ok(count)
+    }
 
-        let mut offset = 0;
-        let config = config::standard();
+    ///Throw away the open transaction, returns the number of writes undone
+    pub fn rollback(&mut self) -> Result<usize> {
+        let transaction = self.transaction.take().ok_or_else(no_transaction)?;
+        let count = transaction.entries.len();
+        self.undo(transaction)?;
+        Ok(count)
+    }
 
-        while offset < data.len() {
-            //read length prefix
-            if offset + 4 > data.len() {
-                break; //incomplete entry at end of file?
-            }
-
-            let len = u32::from_le_bytes([
-                data[offset],
-                data[offset + 1],
-                data[offset + 2],
-                data[offset + 3],
-            ]) as usize;
-            offset += 4;
-            //check if we have the full entry
-            if offset + len > data.len() {
-                //incomplete final entry
-                break;
-            }
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
 
-            //decode the entry
-            let entry_data = &data[offset..offset + len];
-            let (entry, __): (WalEntry, usize) = decode_from_slice(entry_data, config)
-                .map_err(|e| RustyDbErr::SerializationError(e.to_string()))?;
-            //apply the entry to in-memory state
+    ///Log a validated write (or buffer it inside a transaction) and apply it in memory
+    fn log_and_apply(&mut self, entry: WalEntry) -> Result<()> {
+        if self.transaction.is_none() {
+            let at = now_millis();
+            self.write_wal_at(&entry, at)?;
             self.apply_wal_entry(&entry)?;
+            self.touch(&entry, at);
+            //the write is in the WAL, a failed checkpoint is kept for stats and retried later.
+            //Failing the write would have clients retry, and apply INCR twice.
+            self.checkpoint_if_due().ok();
+            return Ok(());
+        }
+        if let WalEntry::Transaction { entries } = entry {
+            //already inside a transaction, just join it
+            for entry in entries {
+                self.log_and_apply(entry)?;
+            }
+            return Ok(());
+        }
+        let undo = self.inverse_of(&entry);
+        self.apply_wal_entry(&entry)?;
+        self.touch(&entry, now_millis());
+        if let Some(transaction) = self.transaction.as_mut() {
+            transaction.entries.push(entry);
+            transaction.undo.push(undo);
         }
         Ok(())
     }
 
-    pub fn apply_wal_entry(&mut self, entry: &WalEntry) -> Result<()> {
+    ///Entries that reverse `entry` when applied after it
+    fn inverse_of(&self, entry: &WalEntry) -> Vec<WalEntry> {
+        let restore = |table: &str, key: &str| match self.tables.get(table).and_then(|t| t.get(key))
+        {
+            Some(old) => WalEntry::Put {
+                table: table.to_string(),
+                key: key.to_string(),
+                val: old.clone(),
+            },
+            None => WalEntry::Delete {
+                table: table.to_string(),
+                key: key.to_string(),
+            },
+        };
+        let restore_expiry = |table: &str, key: &str| WalEntry::Expire {
+            table: table.to_string(),
+            key: key.to_string(),
+            expires_at: self.expiry_of(table, key),
+        };
         match entry {
-            WalEntry::Put { table, key, val } => {
-                self.tables
-                    .entry(table.to_string())
-                    //we are lenient during replay_wal
-                    .or_insert_with(HashMap::new)
-                    .insert(key.to_string(), val.to_string());
+            WalEntry::Put { table, key, .. } | WalEntry::Delete { table, key } => {
+                vec![restore(table, key), restore_expiry(table, key)]
             }
-            WalEntry::Delete { table, key } => {
-                if let Some(t) = self.tables.get_mut(table) {
-                    t.remove(key);
+            WalEntry::Expire { table, key, .. } => vec![restore_expiry(table, key)],
+            WalEntry::CreateTable { table } => vec![WalEntry::DropTable {
+                table: table.to_string(),
+            }],
+            WalEntry::DropTable { table } | WalEntry::TruncateTable { table } => {
+                let mut undo = Vec::new();
+                if matches!(entry, WalEntry::DropTable { .. }) {
+                    undo.push(WalEntry::CreateTable {
+                        table: table.to_string(),
+                    });
                 }
+                if let Some(old) = self.tables.get(table) {
+                    for key in old.keys() {
+                        undo.push(restore(table, key));
+                        undo.push(restore_expiry(table, key));
+                    }
+                }
+                undo
             }
-            WalEntry::CreateTable { table } => {
-                self.tables
-                    .entry(table.to_string())
-                    .or_insert_with(HashMap::new);
-            }
-            WalEntry::DropTable { table } => {
-                self.tables.remove(table);
+            WalEntry::RenameTable { from, to } => vec![WalEntry::RenameTable {
+                from: to.to_string(),
+                to: from.to_string(),
+            }],
+            WalEntry::CopyTable { to, .. } => vec![WalEntry::DropTable {
+                table: to.to_string(),
+            }],
+            WalEntry::SetTableOptions { table, .. } => vec![WalEntry::SetTableOptions {
+                table: table.to_string(),
+                options: self
+                    .meta
+                    .get(table)
+                    .map(|meta| meta.options.clone())
+                    .unwrap_or_default(),
+            }],
+            WalEntry::Transaction { .. } => {
+                unreachable!("transactions are flattened by log_and_apply")
             }
         }
-        Ok(())
     }
 
-    ///wal checkpointing
-    pub fn checkpoint(&mut self) -> Result<()> {
-        self.save_to_disk()?;
-        //truncate wal, cos it's save_to_disk now
-        fs::write(&self.wal_path, &[]).map_err(|e| RustyDbErr::IoError(e.to_string()))?;
+    fn undo(&mut self, transaction: Transaction) -> Result<()> {
+        for entries in transaction.undo.into_iter().rev() {
+            for entry in entries {
+                self.apply_wal_entry(&entry)?;
+            }
+        }
+        //undoing a drop can't bring back when the table was made, so restore it all
+        self.meta = transaction.meta;
         Ok(())
     }
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
 
-    fn test_db_path(name: &str) -> String {
-        format!("/tmp/rusty_db_{}.bin", name)
+    ///Iterate over the key/values of a table whose keys fall in `range`, in key order
+    pub fn range<'a, K: AsRef<str>>(
+        &'a self,
+        table_name: &str,
+        range: impl RangeBounds<K>,
+    ) -> Result<impl Iterator<Item = (&'a String, &'a Value)> + 'a> {
+        let table = self.table(table_name)?;
+        let mut start = range.start_bound().map(|key| key.as_ref().to_string());
+        let mut end = range.end_bound().map(|key| key.as_ref().to_string());
+        //BTreeMap::range panics on inverted ranges, treat them as empty instead
+        if is_inverted(&start, &end) {
+            start = Bound::Included(String::new());
+            end = Bound::Excluded(String::new());
+        }
+        let expiries = self.expiries.get(table_name);
+        let now = now_millis();
+        Ok(table.range((start, end)).filter(move |(key, _)| {
+            expiries
+                .and_then(|expiries| expiries.get(*key))
+                .is_none_or(|expires_at| *expires_at > now)
+        }))
     }
 
-    fn cleanup(path: &str) {
-        fs::remove_file(path).ok();
+    ///Iterate over the key/values of a table whose keys start with `prefix`, in key order
+    pub fn prefix<'a>(
+        &'a self,
+        table: &str,
+        prefix: &'a str,
+    ) -> Result<impl Iterator<Item = (&'a String, &'a Value)> + 'a> {
+        Ok(self
+            .range::<&str>(table, (Bound::Included(prefix), Bound::Unbounded))?
+            .take_while(move |(key, _)| key.starts_with(prefix)))
+    }
+
+    ///Iterate over the keys of a table matching `pattern`, in key order.
+    ///`after` is a cursor, only keys that sort after it are returned.
+    pub fn keys<'a>(
+        &'a self,
+        table: &str,
+        pattern: Option<&'a KeyPattern>,
+        after: Option<&str>,
+    ) -> Result<impl Iterator<Item = &'a String> + 'a> {
+        let prefix = pattern.map_or("", KeyPattern::literal_prefix);
+        //skip straight to the first key that could match
+        let start = match after {
+            Some(after) if after >= prefix => Bound::Excluded(after.to_string()),
+            _ => Bound::Included(prefix.to_string()),
+        };
+        Ok(self
+            .range::<String>(table, (start, Bound::Unbounded))?
+            .take_while(move |(key, _)| key.starts_with(prefix))
+            .map(|(key, _)| key)
+            .filter(move |key| pattern.is_none_or(|pattern| pattern.matches(key))))
+    }
+
+    ///Look at up to `count` keys after `cursor` and return those matching `pattern`.
+    ///Keep calling with the returned cursor until it is back to the start; every key
+    ///that exists for the whole scan is returned exactly once, whatever is written in between.
+    pub fn scan(
+        &self,
+        table: &str,
+        cursor: &Cursor,
+        pattern: Option<&KeyPattern>,
+        count: usize,
+    ) -> Result<ScanPage<'_>> {
+        let prefix = pattern.map_or("", KeyPattern::literal_prefix);
+        let start = match cursor.last_key() {
+            Some(after) if after >= prefix => Bound::Excluded(after.to_string()),
+            _ => Bound::Included(prefix.to_string()),
+        };
+        let mut rows = self
+            .range::<String>(table, (start, Bound::Unbounded))?
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .peekable();
+        let mut entries = Vec::new();
+        let mut last = None;
+        //at least one key, or the scan would never move
+        for (key, val) in rows.by_ref().take(count.max(1)) {
+            last = Some(key);
+            if pattern.is_none_or(|pattern| pattern.matches(key)) {
+                entries.push((key, val));
+            }
+        }
+        let cursor = match (last, rows.peek()) {
+            (Some(last), Some(_)) => Cursor::after(last),
+            _ => Cursor::start(),
+        };
+        Ok(ScanPage { cursor, entries })
+    }
+
+    fn table(&self, table: &str) -> Result<&Table> {
+        self.tables
+            .get(table)
+            .ok_or_else(|| RustyDbErr::TableNotFound(table.to_string()))
+    }
+
+    ///List all the tables
+    pub fn list_tables(&self) -> Vec<String> {
+        self.tables.keys().map(|key| key.to_string()).collect()
+    }
+
+    ///Write a snapshot of all tables. The snapshot goes to a temp file which is
+    ///fsynced and renamed over the old one, so a crash leaves either the old or
+    ///the new snapshot in place, never half of one.
+    pub fn save_to_disk(&mut self) -> Result<()> {
+        let snapshot = SnapshotRef {
+            tables: &self.tables,
+            expiries: &self.expiries,
+            meta: &self.meta,
+            //everything in the current WAL is in memory, so the snapshot holds it
+            wal_generation: Some(self.wal_generation),
+        };
+        write_snapshot(
+            &self.file_path,
+            &encode_snapshot(&snapshot)?,
+            self.crash_before(),
+        )
+    }
+
+    fn snapshot_tmp_path(&self) -> String {
+        snapshot_tmp_path(&self.file_path)
+    }
+
+    ///Where a background checkpoint moves the WAL it is replacing until the snapshot is safe
+    fn old_wal_path(&self) -> String {
+        format!("{}.old", self.wal_path)
+    }
+
+    ///Checkpoint step the tests asked to simulate a crash at
+    fn crash_before(&self) -> Option<CheckpointStep> {
+        #[cfg(test)]
+        return self.crash_before;
+        #[cfg(not(test))]
+        None
+    }
+
+    pub fn load_from_disk(&mut self) -> Result<()> {
+        let config = config::standard();
+        let data = fs::read(&self.file_path)
+            .map_err(|e| RustyDbErr::io(format!("reading snapshot {}", self.file_path), e))?;
+        let snapshot = match data.strip_prefix(SNAPSHOT_MAGIC) {
+            Some(versioned) => {
+                let version = versioned.get(..2).map(|v| u16::from_le_bytes([v[0], v[1]]));
+                let body = versioned.get(2..).unwrap_or_default();
+                if version != Some(SNAPSHOT_VERSION) {
+                    return Err(RustyDbErr::Serialization {
+                        context: format!(
+                            "Unsupported snapshot version {:?}, expected {}",
+                            version, SNAPSHOT_VERSION
+                        ),
+                        source: None,
+                    });
+                }
+                let (snapshot, _len): (Snapshot, usize) = bincode::decode_from_slice(body, config)
+                    .map_err(|e| RustyDbErr::serialization("decoding snapshot", e))?;
+                snapshot
+            }
+            None => decode_legacy_snapshot(&data)?,
+        };
+
+        self.tables = snapshot.tables;
+        self.expiries = snapshot.expiries;
+        self.meta = snapshot.meta;
+        self.wal_generation = snapshot.wal_generation.map_or(0, |covered| covered + 1);
+        //older snapshots know nothing about their tables beyond the keys
+        for table in self.tables.keys() {
+            self.meta.entry(table.to_string()).or_default();
+        }
+        Ok(())
+    }
+
+    ///Replay the wal to reconstruct data
+    pub fn replay_wal(&mut self) -> Result<()> {
+        let mut replay = WalReplay::default();
+        //left behind by a background checkpoint that never finished, its entries come first
+        let old_wal_path = self.old_wal_path();
+        if Path::new(&old_wal_path).exists() {
+            replay = self.replay_wal_file(&old_wal_path)?;
+        }
+        if Path::new(&self.wal_path).exists() {
+            let current = self.replay_wal_file(&self.wal_path.clone())?;
+            replay = WalReplay {
+                entries: replay.entries + current.entries,
+                discarded_bytes: replay.discarded_bytes + current.discarded_bytes,
+                corruption: replay.corruption.or(current.corruption),
+                ..current
+            };
+        }
+        self.last_replay = Some(replay);
+        Ok(())
+    }
+
+    fn replay_wal_file(&mut self, wal_path: &str) -> Result<WalReplay> {
+        let data = fs::read(wal_path)
+            .map_err(|e| RustyDbErr::io(format!("reading WAL {}", wal_path), e))?;
+        let (records, mut replay) = decode_records(&data)?;
+        if replay
+            .generation
+            .is_some_and(|generation| generation < self.wal_generation)
+        {
+            //the checkpoint that wrote the snapshot crashed before it could truncate this WAL,
+            //applying it again would eg copy a table over a copy that already has later writes
+            replay.entries = 0;
+            return Ok(replay);
+        }
+        if let Some(generation) = replay.generation {
+            self.wal_generation = generation;
+        }
+        //apply the entries to in-memory state
+        for record in &records {
+            self.apply_wal_entry(&record.entry)?;
+            if let Some(written_at) = record.written_at {
+                self.touch(&record.entry, written_at);
+            }
+        }
+        if replay.discarded_bytes > 0 {
+            //cut off the bad tail so new records don't land after garbage
+            let file = OpenOptions::new()
+                .write(true)
+                .open(wal_path)
+                .map_err(|e| RustyDbErr::io(format!("opening WAL {}", wal_path), e))?;
+            file.set_len(replay.valid_len)
+                .map_err(|e| RustyDbErr::io(format!("truncating WAL {}", wal_path), e))?;
+        }
+        Ok(replay)
+    }
+
+    ///What the WAL replay at open time applied and threw away, if there was a WAL
+    pub fn last_replay(&self) -> Option<&WalReplay> {
+        self.last_replay.as_ref()
+    }
+
+    pub fn apply_wal_entry(&mut self, entry: &WalEntry) -> Result<()> {
+        match entry {
+            WalEntry::Put { table, key, val } => {
+                self.tables
+                    .entry(table.to_string())
+                    //we are lenient during replay_wal
+                    .or_default()
+                    .insert(key.to_string(), val.clone());
+                self.clear_expiry(table, key);
+            }
+            WalEntry::Delete { table, key } => {
+                if let Some(t) = self.tables.get_mut(table) {
+                    t.remove(key);
+                }
+                self.clear_expiry(table, key);
+            }
+            WalEntry::Expire {
+                table,
+                key,
+                expires_at,
+            } => match expires_at {
+                Some(expires_at) if self.tables.get(table).is_some_and(|t| t.contains_key(key)) => {
+                    self.expiries
+                        .entry(table.to_string())
+                        .or_default()
+                        .insert(key.to_string(), *expires_at);
+                }
+                _ => self.clear_expiry(table, key),
+            },
+            WalEntry::CreateTable { table } => {
+                self.tables.entry(table.to_string()).or_default();
+                self.meta.entry(table.to_string()).or_default();
+            }
+            WalEntry::DropTable { table } => {
+                self.tables.remove(table);
+                self.expiries.remove(table);
+                self.meta.remove(table);
+            }
+            WalEntry::RenameTable { from, to } => {
+                if let Some(moved) = self.tables.remove(from) {
+                    self.tables.insert(to.to_string(), moved);
+                    self.expiries.remove(to);
+                    if let Some(expiries) = self.expiries.remove(from) {
+                        self.expiries.insert(to.to_string(), expiries);
+                    }
+                    let meta = self.meta.remove(from).unwrap_or_default();
+                    self.meta.insert(to.to_string(), meta);
+                }
+            }
+            WalEntry::TruncateTable { table } => {
+                if let Some(t) = self.tables.get_mut(table) {
+                    t.clear();
+                }
+                self.expiries.remove(table);
+            }
+            WalEntry::CopyTable { from, to } => {
+                if let Some(copy) = self.tables.get(from).cloned() {
+                    self.tables.insert(to.to_string(), copy);
+                    self.expiries.remove(to);
+                    if let Some(expiries) = self.expiries.get(from).cloned() {
+                        self.expiries.insert(to.to_string(), expiries);
+                    }
+                    //a new table with the same options, its times are its own
+                    let options = self
+                        .meta
+                        .get(from)
+                        .map(|meta| meta.options.clone())
+                        .unwrap_or_default();
+                    self.meta.insert(
+                        to.to_string(),
+                        TableMeta {
+                            options,
+                            ..TableMeta::default()
+                        },
+                    );
+                }
+            }
+            WalEntry::SetTableOptions { table, options } => {
+                if let Some(meta) = self.meta.get_mut(table) {
+                    meta.options = options.clone();
+                }
+            }
+            WalEntry::Transaction { entries } => {
+                for entry in entries {
+                    self.apply_wal_entry(entry)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    ///Record that `entry` changed its tables at `at` unix millis
+    fn touch(&mut self, entry: &WalEntry, at: u64) {
+        let table = match entry {
+            WalEntry::Transaction { entries } => {
+                for entry in entries {
+                    self.touch(entry, at);
+                }
+                return;
+            }
+            WalEntry::DropTable { .. } => return,
+            WalEntry::RenameTable { to, .. } | WalEntry::CopyTable { to, .. } => to,
+            WalEntry::CreateTable { table }
+            | WalEntry::Put { table, .. }
+            | WalEntry::Delete { table, .. }
+            | WalEntry::Expire { table, .. }
+            | WalEntry::TruncateTable { table }
+            | WalEntry::SetTableOptions { table, .. } => table,
+        };
+        if let Some(meta) = self.meta.get_mut(table) {
+            if matches!(
+                entry,
+                WalEntry::CreateTable { .. } | WalEntry::CopyTable { .. }
+            ) {
+                meta.created_at = Some(at);
+            }
+            meta.modified_at = Some(at);
+        }
+    }
+
+    fn clear_expiry(&mut self, table: &str, key: &str) {
+        if let Some(expiries) = self.expiries.get_mut(table) {
+            expiries.remove(key);
+            if expiries.is_empty() {
+                self.expiries.remove(table);
+            }
+        }
+    }
+
+    ///wal checkpointing
+    pub fn checkpoint(&mut self) -> Result<()> {
+        if self.transaction.is_some() {
+            //the snapshot would contain uncommitted writes
+            return Err(RustyDbErr::TransactionError(
+                "Cannot checkpoint during a transaction".to_string(),
+            ));
+        }
+        //if it failed, the snapshot below covers its old WAL too
+        self.wait_for_checkpoint().ok();
+        let started = Instant::now();
+        self.save_to_disk()?;
+
+        //truncate wal, cos it's save_to_disk now
+        crash_point(self.crash_before(), CheckpointStep::TruncateWal)?;
+        let old_wal_path = self.old_wal_path();
+        if Path::new(&old_wal_path).exists() {
+            fs::remove_file(&old_wal_path)
+                .map_err(|e| RustyDbErr::io(format!("removing {}", old_wal_path), e))?;
+        }
+        let mut wal = File::create(&self.wal_path)
+            .map_err(|e| RustyDbErr::io(format!("creating WAL {}", self.wal_path), e))?;
+        self.wal_generation += 1;
+        wal.write_all(&wal_header(self.wal_generation))
+            .and_then(|_| wal.sync_all())
+            .map_err(|e| RustyDbErr::io(format!("writing WAL header to {}", self.wal_path), e))?;
+        self.wal_len = WAL_HEADER_LEN as u64;
+        self.record_checkpoint(CheckpointInfo {
+            at: now_millis(),
+            took: started.elapsed(),
+            wal_entries: self.operations_since_checkpoint,
+            snapshot_bytes: fs::metadata(&self.file_path).map_or(0, |meta| meta.len()),
+        });
+        self.operations_since_checkpoint = 0;
+        self.last_checkpoint = Instant::now();
+        Ok(())
+    }
+
+    ///Checkpoint if the [`CheckpointPolicy`] says one is due, returns whether one was started.
+    ///Writes call this themselves, call it now and then to catch up on time based checkpoints
+    ///when there are no writes.
+    pub fn checkpoint_if_due(&mut self) -> Result<bool> {
+        if self
+            .background_checkpoint
+            .as_ref()
+            .is_some_and(JoinHandle::is_finished)
+        {
+            //its error is kept for stats
+            self.wait_for_checkpoint().ok();
+        }
+        if self.transaction.is_some()
+            || self.background_checkpoint.is_some()
+            || self.operations_since_checkpoint == 0
+        {
+            return Ok(false);
+        }
+        let policy = self.checkpoint_policy;
+        let due = policy
+            .max_entries
+            .is_some_and(|max| self.operations_since_checkpoint > max)
+            || policy.max_wal_bytes.is_some_and(|max| self.wal_len >= max)
+            || policy
+                .max_interval
+                .is_some_and(|max| self.last_checkpoint.elapsed() >= max);
+        if !due {
+            return Ok(false);
+        }
+        let checkpointed = if policy.background {
+            self.start_background_checkpoint()
+        } else {
+            self.checkpoint()
+        };
+        if let Err(why) = checkpointed {
+            //nobody may be looking at the error, stats will show it
+            self.last_checkpoint_error = Some(why.to_string());
+            return Err(why);
+        }
+        Ok(true)
+    }
+
+    ///Wait for a background checkpoint to finish, returning its error if it failed
+    pub fn wait_for_checkpoint(&mut self) -> Result<()> {
+        let Some(checkpoint) = self.background_checkpoint.take() else {
+            return Ok(());
+        };
+        let finished = checkpoint.join().unwrap_or_else(|_| {
+            Err(RustyDbErr::io(
+                "writing background checkpoint",
+                std::io::Error::other("checkpoint thread panicked"),
+            ))
+        });
+        match finished {
+            Ok(info) => {
+                self.record_checkpoint(info);
+                Ok(())
+            }
+            Err(why) => {
+                self.last_checkpoint_error = Some(why.to_string());
+                Err(why)
+            }
+        }
+    }
+
+    ///Swap the WAL for a fresh one and encode and write the snapshot on another thread,
+    ///which gets a copy of the tables. The old WAL is kept until the snapshot is safely
+    ///on disk, opening the database meanwhile replays it before the new one.
+    fn start_background_checkpoint(&mut self) -> Result<()> {
+        let old_wal_path = self.old_wal_path();
+        if Path::new(&old_wal_path).exists() {
+            //an earlier one failed and its WAL is still needed, catch up in the foreground
+            return self.checkpoint();
+        }
+        let started = Instant::now();
+        //the thread encodes its own copy, writers only wait for the clone
+        let snapshot = Snapshot {
+            tables: self.tables.clone(),
+            expiries: self.expiries.clone(),
+            meta: self.meta.clone(),
+            wal_generation: Some(self.wal_generation),
+        };
+        //entries in the old WAL have to be on disk before any in the new one are
+        if self.durability != Durability::OsBuffered {
+            self.sync_wal()?;
+        }
+        self.wal_file = None;
+        fs::rename(&self.wal_path, &old_wal_path).map_err(|e| {
+            RustyDbErr::io(
+                format!("renaming WAL {} to {}", self.wal_path, old_wal_path),
+                e,
+            )
+        })?;
+        sync_parent_dir(&self.wal_path)?;
+        self.wal_len = 0;
+        //the snapshot holds the renamed WAL, the next write starts the one after it
+        self.wal_generation += 1;
+        let wal_entries = self.operations_since_checkpoint;
+        self.operations_since_checkpoint = 0;
+        self.last_checkpoint = Instant::now();
+
+        let file_path = self.file_path.clone();
+        let crash_before = self.crash_before();
+        self.background_checkpoint = Some(thread::spawn(move || {
+            let encoded = encode_snapshot(&snapshot)?;
+            write_snapshot(&file_path, &encoded, crash_before)?;
+            fs::remove_file(&old_wal_path)
+                .map_err(|e| RustyDbErr::io(format!("removing {}", old_wal_path), e))?;
+            Ok(CheckpointInfo {
+                at: now_millis(),
+                took: started.elapsed(),
+                wal_entries,
+                snapshot_bytes: encoded.len() as u64,
+            })
+        }));
+        Ok(())
+    }
+
+    fn record_checkpoint(&mut self, info: CheckpointInfo) {
+        self.total_checkpoints += 1;
+        self.last_checkpoint_error = None;
+        if self.checkpoints.len() == CHECKPOINT_HISTORY_LEN {
+            self.checkpoints.pop_front();
+        }
+        self.checkpoints.push_back(info);
+    }
+
+    ///Remove expired keys and checkpoint, leaving the smallest snapshot and an empty WAL.
+    ///Returns how many expired keys went.
+    pub fn compact(&mut self) -> Result<usize> {
+        if self.transaction.is_some() {
+            return Err(RustyDbErr::TransactionError(
+                "Cannot compact during a transaction".to_string(),
+            ));
+        }
+        let swept = self.sweep_expired();
+        self.checkpoint()?;
+        Ok(swept)
+    }
+}
+
+///Write `encoded` to a temp file, fsync it and rename it over the snapshot at `file_path`
+fn write_snapshot(
+    file_path: &str,
+    encoded: &[u8],
+    crash_before: Option<CheckpointStep>,
+) -> Result<()> {
+    let tmp_path = snapshot_tmp_path(file_path);
+
+    crash_point(crash_before, CheckpointStep::WriteSnapshot)?;
+    let mut file =
+        File::create(&tmp_path).map_err(|e| RustyDbErr::io(format!("creating {}", tmp_path), e))?;
+    file.write_all(encoded)
+        .map_err(|e| RustyDbErr::io(format!("writing snapshot {}", tmp_path), e))?;
+
+    crash_point(crash_before, CheckpointStep::SyncSnapshot)?;
+    file.sync_all()
+        .map_err(|e| RustyDbErr::io(format!("syncing snapshot {}", tmp_path), e))?;
+
+    crash_point(crash_before, CheckpointStep::RenameSnapshot)?;
+    fs::rename(&tmp_path, file_path)
+        .map_err(|e| RustyDbErr::io(format!("renaming {} to {}", tmp_path, file_path), e))?;
+
+    //the rename itself only survives power loss once the directory is synced
+    crash_point(crash_before, CheckpointStep::SyncDir)?;
+    sync_parent_dir(file_path)?;
+
+    Ok(())
+}
+
+fn snapshot_tmp_path(file_path: &str) -> String {
+    format!("{}.tmp", file_path)
+}
+
+///Fail with a simulated crash if the tests asked for one at `step`
+fn crash_point(crash_before: Option<CheckpointStep>, step: CheckpointStep) -> Result<()> {
+    if crash_before == Some(step) {
+        return Err(RustyDbErr::io(
+            "writing checkpoint",
+            std::io::Error::other(format!("simulated crash before {:?}", step)),
+        ));
+    }
+    Ok(())
+}
+
+///A snapshot file's bytes, header first
+fn encode_snapshot(snapshot: &impl Encode) -> Result<Vec<u8>> {
+    let mut encoded = SNAPSHOT_MAGIC.to_vec();
+    encoded.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    encoded.extend(
+        encode_to_vec(snapshot, config::standard())
+            .map_err(|e| RustyDbErr::serialization("encoding snapshot", e))?,
+    );
+    Ok(encoded)
+}
+
+///Snapshots from before the header, which held just the tables with string values
+fn decode_legacy_snapshot(data: &[u8]) -> Result<Snapshot> {
+    let (tables, _len): (BTreeMap<String, BTreeMap<String, String>>, usize) =
+        bincode::decode_from_slice(data, config::standard())
+            .map_err(|e| RustyDbErr::serialization("decoding legacy snapshot", e))?;
+    let tables = tables
+        .into_iter()
+        .map(|(name, table)| {
+            let table = table
+                .into_iter()
+                .map(|(key, val)| (key, Value::Str(val)))
+                .collect();
+            (name, table)
+        })
+        .collect();
+    Ok(Snapshot {
+        tables,
+        expiries: BTreeMap::new(),
+        meta: BTreeMap::new(),
+        wal_generation: None,
+    })
+}
+
+///Open the WAL for appending, writing the header if the file is new
+fn open_wal(wal_path: &str, generation: u64) -> Result<File> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(wal_path)
+        .map_err(|e| RustyDbErr::io(format!("opening WAL {}", wal_path), e))?;
+    let wal_len = file
+        .metadata()
+        .map_err(|e| RustyDbErr::io(format!("reading WAL {}", wal_path), e))?
+        .len();
+    if wal_len == 0 {
+        file.write_all(&wal_header(generation))
+            .map_err(|e| RustyDbErr::io(format!("writing WAL header to {}", wal_path), e))?;
+    }
+    Ok(file)
+}
+
+///fsync the directory holding `path`, a no-op where directories can't be opened
+fn sync_parent_dir(path: &str) -> Result<()> {
+    #[cfg(unix)]
+    {
+        let dir = match Path::new(path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .map_err(|e| RustyDbErr::io(format!("syncing directory {}", dir.display()), e))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+fn not_integer(key: &str) -> RustyDbErr {
+    RustyDbErr::WrongType(format!("{} does not hold an integer", key))
+}
+
+fn not_float(key: &str) -> RustyDbErr {
+    RustyDbErr::WrongType(format!("{} does not hold a number", key))
+}
+
+fn no_transaction() -> RustyDbErr {
+    RustyDbErr::TransactionError("No transaction in progress".to_string())
+}
+
+fn is_inverted(start: &Bound<String>, end: &Bound<String>) -> bool {
+    match (start, end) {
+        (Bound::Included(s), Bound::Included(e)) => s > e,
+        (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) => {
+            s >= e
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wal::WAL_MAGIC;
+
+    fn test_db_path(name: &str) -> String {
+        format!("/tmp/rusty_db_{}.bin", name)
+    }
+
+    fn cleanup(path: &str) {
+        fs::remove_file(path).ok();
+        fs::remove_file(format!("{}.tmp", path)).ok();
+        fs::remove_file(format!("{}.wal", path)).ok();
+        fs::remove_file(format!("{}.wal.old", path)).ok();
     }
 
     #[test]
@@ -341,7 +1987,7 @@ mod test {
             "key1".to_string(),
             "val1".to_string(),
         )?;
-        assert_eq!(Ok(&"val1".to_string()), db.get("test_table", "key1"));
+        assert_eq!(Ok(&Value::from("val1")), db.get("test_table", "key1"));
         cleanup(&path);
         Ok(())
     }
@@ -557,4 +2203,1231 @@ mod test {
         cleanup(&path);
         Ok(())
     }
+
+    #[test]
+    fn test_builder_custom_wal_path() -> Result<()> {
+        let path = test_db_path("builder");
+        let wal_path = test_db_path("builder_custom_wal");
+        cleanup(&path);
+        cleanup(&wal_path);
+        {
+            let mut db = RustyDb::builder(&path).wal_path(&wal_path).open()?;
+            db.create_table("users")?;
+            db.put("users".to_string(), "id1".to_string(), "alice".to_string())?;
+            assert_eq!(db.wal_path(), wal_path);
+        }
+        assert!(Path::new(&wal_path).exists());
+        {
+            let db = RustyDb::builder(&path).wal_path(&wal_path).open()?;
+            assert_eq!(db.get("users", "id1")?, &"alice".to_string());
+        }
+        cleanup(&path);
+        cleanup(&wal_path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_and_prefix() -> Result<()> {
+        let path = test_db_path("range");
+        cleanup(&path);
+        let mut db = RustyDb::new(&path)?;
+        db.create_table("events")?;
+        for key in ["user:2", "user:1", "order:1", "user:10", "zebra"] {
+            db.put("events".to_string(), key.to_string(), format!("v-{}", key))?;
+        }
+        let keys: Vec<&String> = db
+            .range("events", "user:1".."user:2")?
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(keys, vec!["user:1", "user:10"]);
+        let keys: Vec<&String> = db.range::<&str>("events", ..)?.map(|(k, _)| k).collect();
+        assert_eq!(
+            keys,
+            vec!["order:1", "user:1", "user:10", "user:2", "zebra"]
+        );
+        assert_eq!(db.range("events", "z".."a")?.count(), 0);
+        let keys: Vec<&String> = db.prefix("events", "user:")?.map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!["user:1", "user:10", "user:2"]);
+        assert_eq!(
+            db.execute(
+                crate::command::parse("SCAN events FROM order TO user:10 LIMIT 2").unwrap()
+            )?
+            .to_string(),
+            "order:1: \"v-order:1\"\nuser:1: \"v-user:1\""
+        );
+        assert!(db.range::<&str>("missing", ..).is_err());
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_table() -> Result<()> {
+        let path = test_db_path("drop_table");
+        cleanup(&path);
+        {
+            let mut db = RustyDb::new(&path)?;
+            db.create_table("users")?;
+            db.drop_table("users")?;
+            assert!(db.list_tables().is_empty());
+            assert_eq!(
+                db.drop_table("users"),
+                Err(RustyDbErr::TableNotFound("users".to_string()))
+            );
+        }
+        let db = RustyDb::new(&path)?;
+        assert!(db.list_tables().is_empty());
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_put_is_not_logged() -> Result<()> {
+        let path = test_db_path("failed_put");
+        cleanup(&path);
+        {
+            let mut db = RustyDb::new(&path)?;
+            assert!(
+                db.put("missing".to_string(), "k".to_string(), "v".to_string())
+                    .is_err()
+            );
+        }
+        let db = RustyDb::new(&path)?;
+        assert!(db.list_tables().is_empty());
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_commit() -> Result<()> {
+        let path = test_db_path("txn_commit");
+        cleanup(&path);
+        {
+            let mut db = RustyDb::new(&path)?;
+            db.begin()?;
+            db.create_table("accounts")?;
+            db.put(
+                "accounts".to_string(),
+                "alice".to_string(),
+                "50".to_string(),
+            )?;
+            db.put("accounts".to_string(), "bob".to_string(), "150".to_string())?;
+            //reads see the transaction's own writes
+            assert_eq!(db.get("accounts", "bob")?, &"150".to_string());
+            assert_eq!(
+                db.begin(),
+                Err(RustyDbErr::TransactionError(
+                    "Transaction already in progress".to_string()
+                ))
+            );
+            assert_eq!(db.commit()?, 3);
+            assert!(!db.in_transaction());
+        }
+        let db = RustyDb::new(&path)?;
+        assert_eq!(db.get("accounts", "alice")?, &"50".to_string());
+        assert_eq!(db.get("accounts", "bob")?, &"150".to_string());
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_rollback() -> Result<()> {
+        let path = test_db_path("txn_rollback");
+        cleanup(&path);
+        {
+            let mut db = RustyDb::new(&path)?;
+            db.create_table("accounts")?;
+            db.put(
+                "accounts".to_string(),
+                "alice".to_string(),
+                "100".to_string(),
+            )?;
+            db.begin()?;
+            db.put("accounts".to_string(), "alice".to_string(), "0".to_string())?;
+            db.put("accounts".to_string(), "bob".to_string(), "100".to_string())?;
+            db.drop_table("accounts")?;
+            db.create_table("audit")?;
+            assert_eq!(db.rollback()?, 4);
+            assert_eq!(db.list_tables(), vec!["accounts".to_string()]);
+            assert_eq!(db.get("accounts", "alice")?, &"100".to_string());
+            assert!(db.get("accounts", "bob").is_err());
+            assert!(db.rollback().is_err());
+            //uncommitted writes never reach the WAL
+            db.begin()?;
+            db.put("accounts".to_string(), "carol".to_string(), "1".to_string())?;
+        }
+        let db = RustyDb::new(&path)?;
+        assert_eq!(db.get("accounts", "alice")?, &"100".to_string());
+        assert!(db.get("accounts", "carol").is_err());
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_torn_transaction_is_not_replayed() -> Result<()> {
+        let path = test_db_path("txn_torn");
+        cleanup(&path);
+        {
+            let mut db = RustyDb::new(&path)?;
+            db.create_table("accounts")?;
+            db.begin()?;
+            db.put(
+                "accounts".to_string(),
+                "alice".to_string(),
+                "50".to_string(),
+            )?;
+            db.put("accounts".to_string(), "bob".to_string(), "150".to_string())?;
+            db.commit()?;
+        }
+        //chop the end off the transaction record, as if we crashed mid write
+        let wal_path = format!("{}.wal", path);
+        let wal = fs::read(&wal_path).unwrap();
+        fs::write(&wal_path, &wal[..wal.len() - 3]).unwrap();
+        let db = RustyDb::new(&path)?;
+        assert!(db.get("accounts", "alice").is_err());
+        assert!(db.get("accounts", "bob").is_err());
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_wal_tail_is_truncated() -> Result<()> {
+        let path = test_db_path("wal_corrupt");
+        let wal_path = format!("{}.wal", path);
+        cleanup(&path);
+        {
+            let mut db = RustyDb::new(&path)?;
+            db.create_table("users")?;
+            db.put("users".to_string(), "u1".to_string(), "alice".to_string())?;
+        }
+        let good_len = fs::metadata(&wal_path).unwrap().len();
+        {
+            let mut db = RustyDb::new(&path)?;
+            db.put("users".to_string(), "u2".to_string(), "bob".to_string())?;
+        }
+        //flip a bit in the last record's payload
+        let mut wal = fs::read(&wal_path).unwrap();
+        let last = wal.len() - 1;
+        wal[last] ^= 0x01;
+        fs::write(&wal_path, &wal).unwrap();
+        {
+            let mut db = RustyDb::new(&path)?;
+            let replay = db.last_replay().unwrap();
+            assert_eq!(replay.entries, 2);
+            assert_eq!(replay.discarded_bytes, wal.len() as u64 - good_len);
+            assert!(replay.corruption.is_some());
+            assert_eq!(fs::metadata(&wal_path).unwrap().len(), good_len);
+            assert_eq!(db.get("users", "u1")?, &"alice".to_string());
+            assert!(db.get("users", "u2").is_err());
+            db.put("users".to_string(), "u3".to_string(), "carol".to_string())?;
+        }
+        //writes after the truncation replay cleanly
+        let db = RustyDb::new(&path)?;
+        assert_eq!(db.last_replay().unwrap().corruption, None);
+        assert_eq!(db.get("users", "u3")?, &"carol".to_string());
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoint_survives_crash_at_every_step() -> Result<()> {
+        let steps = [
+            CheckpointStep::WriteSnapshot,
+            CheckpointStep::SyncSnapshot,
+            CheckpointStep::RenameSnapshot,
+            CheckpointStep::SyncDir,
+            CheckpointStep::TruncateWal,
+        ];
+        for step in steps {
+            let path = test_db_path(&format!("crash_{:?}", step));
+            cleanup(&path);
+            {
+                let mut db = RustyDb::new(&path)?;
+                db.create_table("users")?;
+                db.put("users".to_string(), "u1".to_string(), "alice".to_string())?;
+                //an older snapshot is on disk when we crash
+                db.checkpoint()?;
+                db.put("users".to_string(), "u1".to_string(), "alicia".to_string())?;
+                db.put("users".to_string(), "u2".to_string(), "bob".to_string())?;
+                db.crash_before = Some(step);
+                assert!(db.checkpoint().is_err(), "no crash at {:?}", step);
+            }
+            if step == CheckpointStep::SyncSnapshot {
+                //the temp snapshot may be torn too
+                fs::write(format!("{}.tmp", path), b"half a snaps").unwrap();
+            }
+            {
+                let mut db = RustyDb::new(&path)?;
+                assert_eq!(db.get("users", "u1")?, &"alicia".to_string(), "{:?}", step);
+                assert_eq!(db.get("users", "u2")?, &"bob".to_string(), "{:?}", step);
+                assert!(!Path::new(&format!("{}.tmp", path)).exists());
+                db.checkpoint()?;
+            }
+            let db = RustyDb::new(&path)?;
+            assert_eq!(db.get("users", "u1")?, &"alicia".to_string());
+            assert_eq!(db.last_replay().unwrap().entries, 0);
+            cleanup(&path);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_wal_held_by_snapshot_is_not_replayed() -> Result<()> {
+        let path = test_db_path("wal_held_by_snapshot");
+        let crash_after_snapshot = |db: &mut RustyDb| {
+            db.crash_before = Some(CheckpointStep::TruncateWal);
+            assert!(db.checkpoint().is_err());
+        };
+
+        //replaying the COPY again would copy a's later key into b
+        cleanup(&path);
+        {
+            let mut db = RustyDb::new(&path)?;
+            db.create_table("a")?;
+            db.put("a".to_string(), "k".to_string(), "v")?;
+            db.copy_table("a", "b")?;
+            db.put("a".to_string(), "k2".to_string(), "v")?;
+            crash_after_snapshot(&mut db);
+        }
+        {
+            let mut db = RustyDb::new(&path)?;
+            assert_eq!(db.last_replay().unwrap().entries, 0);
+            assert_eq!(db.get("a", "k2")?, &Value::from("v"));
+            assert!(db.get("b", "k2").is_err());
+            //the covered WAL was replaced on open, new writes survive the next one
+            db.put("b".to_string(), "k3".to_string(), "v")?;
+        }
+        let db = RustyDb::new(&path)?;
+        assert_eq!(db.last_replay().unwrap().entries, 1);
+        assert_eq!(db.get("b", "k3")?, &Value::from("v"));
+        assert!(db.get("b", "k2").is_err());
+
+        //replaying the RENAME again would move the new a over b
+        cleanup(&path);
+        {
+            let mut db = RustyDb::new(&path)?;
+            db.create_table("a")?;
+            db.put("a".to_string(), "old".to_string(), "v")?;
+            db.rename_table("a", "b")?;
+            db.create_table("a")?;
+            db.put("a".to_string(), "new".to_string(), "v")?;
+            crash_after_snapshot(&mut db);
+        }
+        let db = RustyDb::new(&path)?;
+        assert_eq!(db.get("b", "old")?, &Value::from("v"));
+        assert_eq!(db.get("a", "new")?, &Value::from("v"));
+        assert!(db.get("b", "new").is_err());
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_durability_modes() -> Result<()> {
+        let modes = [
+            Durability::Always,
+            Durability::GroupCommit {
+                interval: std::time::Duration::from_secs(3600),
+                max_entries: 3,
+            },
+            Durability::OsBuffered,
+        ];
+        for (i, durability) in modes.into_iter().enumerate() {
+            let path = test_db_path(&format!("durability_{}", i));
+            cleanup(&path);
+            {
+                let mut db = RustyDb::builder(&path).durability(durability).open()?;
+                db.create_table("t")?;
+                db.put("t".to_string(), "k1".to_string(), "v1".to_string())?;
+                let expected_unsynced = match durability {
+                    Durability::Always => 0,
+                    _ => 2,
+                };
+                assert_eq!(db.unsynced_wal_entries(), expected_unsynced);
+                db.put("t".to_string(), "k2".to_string(), "v2".to_string())?;
+                let expected_unsynced = match durability {
+                    Durability::OsBuffered => 3,
+                    _ => 0,
+                };
+                assert_eq!(db.unsynced_wal_entries(), expected_unsynced);
+                db.put("t".to_string(), "k3".to_string(), "v3".to_string())?;
+            }
+            let db = RustyDb::new(&path)?;
+            assert_eq!(db.get("t", "k3")?, &"v3".to_string());
+            cleanup(&path);
+        }
+
+        //a record written just before going quiet is synced once the interval is up
+        let path = test_db_path("durability_timed");
+        cleanup(&path);
+        let durability = Durability::GroupCommit {
+            interval: Duration::from_millis(20),
+            max_entries: 100,
+        };
+        let mut db = RustyDb::builder(&path).durability(durability).open()?;
+        db.create_table("t")?;
+        db.sync_wal()?;
+        db.put("t".to_string(), "k1".to_string(), "v1".to_string())?;
+        assert!(!db.sync_wal_if_due()?);
+        assert_eq!(db.unsynced_wal_entries(), 1);
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(db.sync_wal_if_due()?);
+        assert_eq!(db.unsynced_wal_entries(), 0);
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_expiry() -> Result<()> {
+        let path = test_db_path("expiry");
+        cleanup(&path);
+        {
+            let mut db = RustyDb::new(&path)?;
+            db.create_table("sessions")?;
+            db.put_with_ttl(
+                "sessions".to_string(),
+                "gone".to_string(),
+                "v".to_string(),
+                Duration::ZERO,
+            )?;
+            db.put_with_ttl(
+                "sessions".to_string(),
+                "later".to_string(),
+                "v".to_string(),
+                Duration::from_secs(60),
+            )?;
+            db.put(
+                "sessions".to_string(),
+                "forever".to_string(),
+                "v".to_string(),
+            )?;
+            //lazy expiry hides the key straight away
+            assert_eq!(
+                db.get("sessions", "gone"),
+                Err(RustyDbErr::KeyNotFound("gone".to_string()))
+            );
+            assert_eq!(db.range::<&str>("sessions", ..)?.count(), 2);
+            let left = db.ttl("sessions", "later")?.unwrap();
+            assert!(left > Duration::from_secs(59) && left <= Duration::from_secs(60));
+            assert_eq!(db.ttl("sessions", "forever")?, None);
+            assert_eq!(db.sweep_expired(), 1);
+        }
+        {
+            let mut db = RustyDb::new(&path)?;
+            assert!(db.get("sessions", "gone").is_err());
+            assert!(db.ttl("sessions", "later")?.is_some());
+            assert!(db.persist("sessions", "later")?);
+            assert!(!db.persist("sessions", "later")?);
+            db.expire("sessions", "forever", Duration::from_secs(30))?;
+            db.checkpoint()?;
+        }
+        {
+            //expiries survive in the snapshot too
+            let mut db = RustyDb::new(&path)?;
+            assert_eq!(db.ttl("sessions", "later")?, None);
+            assert!(db.ttl("sessions", "forever")?.is_some());
+            //a plain SET clears the expiry
+            db.put(
+                "sessions".to_string(),
+                "forever".to_string(),
+                "v2".to_string(),
+            )?;
+            assert_eq!(db.ttl("sessions", "forever")?, None);
+            //just over u64::MAX millis, which used to wrap round to 384ms
+            db.expire(
+                "sessions",
+                "forever",
+                Duration::from_secs(18_446_744_073_709_552),
+            )?;
+            assert!(db.ttl("sessions", "forever")?.unwrap() > Duration::from_secs(3600));
+            //EXPIRE 0 deletes the key, as in redis
+            db.execute(crate::command::parse("EXPIRE sessions forever 0").unwrap())?;
+            assert!(db.get("sessions", "forever").is_err());
+        }
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollback_restores_expiry() -> Result<()> {
+        let path = test_db_path("expiry_rollback");
+        cleanup(&path);
+        let mut db = RustyDb::new(&path)?;
+        db.create_table("sessions")?;
+        db.put_with_ttl(
+            "sessions".to_string(),
+            "s1".to_string(),
+            "v".to_string(),
+            Duration::from_secs(60),
+        )?;
+        db.begin()?;
+        db.put("sessions".to_string(), "s1".to_string(), "v2".to_string())?;
+        assert_eq!(db.ttl("sessions", "s1")?, None);
+        db.rollback()?;
+        assert_eq!(db.get("sessions", "s1")?, &"v".to_string());
+        assert!(db.ttl("sessions", "s1")?.is_some());
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_typed_values_persist() -> Result<()> {
+        let path = test_db_path("typed_values");
+        cleanup(&path);
+        let list = Value::List(vec![Value::Int(1), Value::Null, Value::Bytes(vec![0xff])]);
+        {
+            let mut db = RustyDb::new(&path)?;
+            db.create_table("t")?;
+            db.put("t".to_string(), "int".to_string(), 7)?;
+            db.put("t".to_string(), "float".to_string(), 0.5)?;
+            db.put("t".to_string(), "list".to_string(), list.clone())?;
+            db.checkpoint()?;
+            db.put("t".to_string(), "flag".to_string(), true)?;
+        }
+        let mut db = RustyDb::new(&path)?;
+        assert_eq!(db.get("t", "int")?, &Value::Int(7));
+        assert_eq!(db.get("t", "float")?, &Value::Float(0.5));
+        assert_eq!(db.get("t", "list")?, &list);
+        assert_eq!(db.get("t", "flag")?, &Value::Bool(true));
+        assert_eq!(
+            db.execute(crate::command::parse("GET t list").unwrap())?
+                .to_string(),
+            "[1, null, x'ff']"
+        );
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_legacy_snapshot() -> Result<()> {
+        let path = test_db_path("legacy_snapshot");
+        cleanup(&path);
+        let mut tables: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+        tables
+            .entry("users".to_string())
+            .or_default()
+            .insert("u1".to_string(), "alice".to_string());
+        fs::write(&path, encode_to_vec(&tables, config::standard()).unwrap()).unwrap();
+        let db = RustyDb::new(&path)?;
+        assert_eq!(db.get("users", "u1")?, &Value::from("alice"));
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_legacy_wal() -> Result<()> {
+        let path = test_db_path("legacy_wal");
+        cleanup(&path);
+        //the baseline's CreateTable then Put, variant index then fields, framed by length alone
+        let mut wal = Vec::new();
+        for payload in [
+            encode_to_vec((2u32, "users"), config::standard()).unwrap(),
+            encode_to_vec((0u32, "users", "u1", "alice"), config::standard()).unwrap(),
+        ] {
+            wal.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            wal.extend_from_slice(&payload);
+        }
+        fs::write(format!("{}.wal", path), wal).unwrap();
+        {
+            let db = RustyDb::new(&path)?;
+            assert_eq!(db.last_replay().unwrap().entries, 2);
+            assert_eq!(db.get("users", "u1")?, &Value::from("alice"));
+        }
+        //it was checkpointed into the snapshot and swapped for a current WAL
+        let data = fs::read(format!("{}.wal", path)).unwrap();
+        assert!(data.starts_with(WAL_MAGIC));
+        let db = RustyDb::new(&path)?;
+        assert_eq!(db.last_replay().unwrap().entries, 0);
+        assert_eq!(db.get("users", "u1")?, &Value::from("alice"));
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_counters() -> Result<()> {
+        let path = test_db_path("counters");
+        cleanup(&path);
+        {
+            let mut db = RustyDb::new(&path)?;
+            db.create_table("stats")?;
+            assert_eq!(db.incr_by("stats", "hits", 1)?, 1);
+            assert_eq!(db.incr_by("stats", "hits", 41)?, 42);
+            db.put("stats".to_string(), "from_redis".to_string(), "7")?;
+            assert_eq!(db.incr_by("stats", "from_redis", -1)?, 6);
+            assert_eq!(db.incr_by_float("stats", "hits", 0.5)?, 42.5);
+            assert!(matches!(
+                db.incr_by("stats", "hits", 1),
+                Err(RustyDbErr::WrongType(_))
+            ));
+            db.put("stats".to_string(), "name".to_string(), "alice")?;
+            assert!(matches!(
+                db.incr_by_float("stats", "name", 1.0),
+                Err(RustyDbErr::WrongType(_))
+            ));
+            db.put("stats".to_string(), "max".to_string(), i64::MAX)?;
+            assert!(db.incr_by("stats", "max", 1).is_err());
+            assert!(matches!(
+                db.incr_by("missing", "hits", 1),
+                Err(RustyDbErr::TableNotFound(_))
+            ));
+
+            db.put_with_ttl(
+                "stats".to_string(),
+                "window".to_string(),
+                1,
+                Duration::from_secs(100),
+            )?;
+            db.incr_by("stats", "window", 1)?;
+            assert!(db.ttl("stats", "window")?.is_some());
+        }
+        let mut db = RustyDb::new(&path)?;
+        assert_eq!(db.get("stats", "hits")?, &Value::Float(42.5));
+        assert_eq!(db.get("stats", "from_redis")?, &Value::Int(6));
+        assert_eq!(db.get("stats", "window")?, &Value::Int(2));
+        assert!(db.ttl("stats", "window")?.is_some());
+        assert_eq!(
+            db.execute(crate::command::parse("DECRBY stats from_redis 10").unwrap())?
+                .to_string(),
+            "-4"
+        );
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_conditional_puts() -> Result<()> {
+        let path = test_db_path("conditional_puts");
+        cleanup(&path);
+        let mut db = RustyDb::new(&path)?;
+        db.create_table("t")?;
+        let put_if = |db: &mut RustyDb, val: i64, condition: PutCondition| {
+            db.put_if("t".to_string(), "k".to_string(), val, &condition, None)
+        };
+        assert!(!put_if(&mut db, 1, PutCondition::IfPresent)?);
+        assert!(put_if(&mut db, 1, PutCondition::IfAbsent)?);
+        assert!(!put_if(&mut db, 2, PutCondition::IfAbsent)?);
+        assert!(put_if(&mut db, 2, PutCondition::IfPresent)?);
+        assert!(!put_if(&mut db, 3, PutCondition::IfEquals(Value::Int(1)))?);
+        assert!(put_if(&mut db, 3, PutCondition::IfEquals(Value::Int(2)))?);
+        //types matter, "3" is not 3
+        assert!(!put_if(
+            &mut db,
+            4,
+            PutCondition::IfEquals(Value::from("3"))
+        )?);
+        assert_eq!(db.get("t", "k")?, &Value::Int(3));
+        assert!(matches!(
+            db.put_if(
+                "missing".to_string(),
+                "k".to_string(),
+                1,
+                &PutCondition::IfAbsent,
+                None
+            ),
+            Err(RustyDbErr::TableNotFound(_))
+        ));
+
+        let parse = |input: &str| crate::command::parse(input).unwrap();
+        assert_eq!(db.execute(parse("CAS t k 2 5"))?.to_string(), "Not written");
+        assert_eq!(db.execute(parse("CAS t k 3 5"))?.to_string(), "Ok");
+        assert_eq!(db.execute(parse("SETNX t other 1"))?.to_string(), "Ok");
+        assert_eq!(
+            db.execute(parse("SET t other 2 NX"))?.to_string(),
+            "Not written"
+        );
+        assert_eq!(
+            db.execute(parse("SET t new 1 XX"))?.to_string(),
+            "Not written"
+        );
+        drop(db);
+
+        let db = RustyDb::new(&path)?;
+        assert_eq!(db.get("t", "k")?, &Value::Int(5));
+        assert_eq!(db.get("t", "other")?, &Value::Int(1));
+        assert!(db.get("t", "new").is_err());
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_batches() -> Result<()> {
+        let path = test_db_path("batches");
+        cleanup(&path);
+        let mut db = RustyDb::new(&path)?;
+        db.create_table("t")?;
+        db.mset(
+            "t",
+            vec![
+                ("a".to_string(), Value::Int(1)),
+                ("b".to_string(), Value::Int(2)),
+                ("c".to_string(), Value::Int(3)),
+            ],
+        )?;
+        //one record for the whole batch
+        assert_eq!(db.operations_since_checkpoint(), 2);
+        assert_eq!(
+            db.mget("t", &["a", "missing", "c"])?,
+            vec![Some(&Value::Int(1)), None, Some(&Value::Int(3))]
+        );
+        assert_eq!(db.mdel("t", &["a", "a", "missing", "b"])?, 2);
+        assert_eq!(db.mdel("t", &["missing"])?, 0);
+        assert!(matches!(
+            db.mget("missing", &["a"]),
+            Err(RustyDbErr::TableNotFound(_))
+        ));
+
+        let parse = |input: &str| crate::command::parse(input).unwrap();
+        assert_eq!(
+            db.execute(parse("MSET t x 1 y \"two\""))?.to_string(),
+            "Set 2 keys"
+        );
+        assert_eq!(
+            db.execute(parse("MGET t x y z"))?.to_string(),
+            "x: 1\ny: \"two\"\nz: (not found)"
+        );
+        assert_eq!(
+            db.execute(parse("MDEL t x z"))?.to_string(),
+            "Deleted 1 keys"
+        );
+        drop(db);
+
+        let db = RustyDb::new(&path)?;
+        assert_eq!(db.list_tables(), vec!["t".to_string()]);
+        assert_eq!(
+            db.mget("t", &["a", "c", "x", "y"])?,
+            vec![None, Some(&Value::Int(3)), None, Some(&Value::from("two"))]
+        );
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_keys_and_count() -> Result<()> {
+        let path = test_db_path("keys_and_count");
+        cleanup(&path);
+        let mut db = RustyDb::new(&path)?;
+        db.create_table("t")?;
+        for key in ["user:1", "user:2", "user:10", "order:1", "users"] {
+            db.put("t".to_string(), key.to_string(), 1)?;
+        }
+        let pattern = KeyPattern::parse("user:*").unwrap();
+        let keys: Vec<&String> = db.keys("t", Some(&pattern), None)?.collect();
+        assert_eq!(keys, vec!["user:1", "user:10", "user:2"]);
+        let keys: Vec<&String> = db.keys("t", Some(&pattern), Some("user:10"))?.collect();
+        assert_eq!(keys, vec!["user:2"]);
+        let regex = KeyPattern::parse("/:1/").unwrap();
+        let keys: Vec<&String> = db.keys("t", Some(&regex), None)?.collect();
+        assert_eq!(keys, vec!["order:1", "user:1", "user:10"]);
+        assert_eq!(db.keys("t", None, None)?.count(), 5);
+        assert!(matches!(
+            db.keys("missing", None, None),
+            Err(RustyDbErr::TableNotFound(_))
+        ));
+
+        let parse = |input: &str| crate::command::parse(input).unwrap();
+        assert_eq!(
+            db.execute(parse("KEYS t user:* LIMIT 2"))?.to_string(),
+            "user:1\nuser:10\n-- more keys, continue with AFTER \"user:10\""
+        );
+        assert_eq!(
+            db.execute(parse("KEYS t user:* LIMIT 2 AFTER user:10"))?
+                .to_string(),
+            "user:2"
+        );
+        assert_eq!(
+            db.execute(parse("KEYS t nobody*"))?.to_string(),
+            "No keys found"
+        );
+        assert_eq!(db.execute(parse("COUNT t"))?.to_string(), "5");
+        assert_eq!(db.execute(parse("COUNT t user?"))?.to_string(), "1");
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_cursor_survives_writes() -> Result<()> {
+        let path = test_db_path("scan_cursor");
+        cleanup(&path);
+        let mut db = RustyDb::new(&path)?;
+        db.create_table("t")?;
+        for i in 0..20 {
+            db.put("t".to_string(), format!("k{:02}", i), i)?;
+        }
+        let mut seen = Vec::new();
+        let mut cursor = Cursor::start();
+        let mut pages = 0;
+        loop {
+            let page = db.scan("t", &cursor, None, 3)?;
+            seen.extend(page.entries.iter().map(|(key, _)| key.to_string()));
+            cursor = page.cursor;
+            pages += 1;
+            if cursor.is_start() {
+                break;
+            }
+            //writes between pages: new keys, deleted keys, rewritten keys
+            db.put("t".to_string(), format!("k{:02}a", pages), 0)?;
+            db.delete("t", &format!("k{:02}", 19 - pages))?;
+            db.put("t".to_string(), "k00".to_string(), 100)?;
+        }
+        //every key that lived through the whole scan, exactly once
+        for i in 0..13 {
+            let key = format!("k{:02}", i);
+            assert_eq!(seen.iter().filter(|k| **k == key).count(), 1, "{}", key);
+        }
+        assert!(pages > 1);
+
+        let pattern = KeyPattern::parse("k1*").unwrap();
+        let page = db.scan("t", &Cursor::start(), Some(&pattern), 100)?;
+        assert!(page.cursor.is_start());
+        assert!(page.entries.iter().all(|(key, _)| key.starts_with("k1")));
+
+        let parse = |input: &str| crate::command::parse(input).unwrap();
+        let first = db.execute(parse("SCAN t 0 MATCH k0? COUNT 2"))?.to_string();
+        assert_eq!(
+            first,
+            format!("cursor: {}\nk00: 100\nk01: 1", Cursor::after("k01"))
+        );
+        assert!(matches!(
+            db.scan("missing", &Cursor::start(), None, 10),
+            Err(RustyDbErr::TableNotFound(_))
+        ));
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_truncate_copy() -> Result<()> {
+        let path = test_db_path("rename_truncate_copy");
+        cleanup(&path);
+        {
+            let mut db = RustyDb::new(&path)?;
+            db.create_table("rates")?;
+            db.put("rates".to_string(), "gbp".to_string(), 1.25)?;
+            db.put_with_ttl(
+                "rates".to_string(),
+                "eur".to_string(),
+                1.08,
+                Duration::from_secs(100),
+            )?;
+            db.copy_table("rates", "rates_next")?;
+            assert!(db.ttl("rates_next", "eur")?.is_some());
+            assert!(matches!(
+                db.copy_table("rates", "rates_next"),
+                Err(RustyDbErr::TableExists(_))
+            ));
+            assert!(matches!(
+                db.rename_table("missing", "other"),
+                Err(RustyDbErr::TableNotFound(_))
+            ));
+
+            //blue/green swap, all or nothing
+            db.begin()?;
+            db.put("rates_next".to_string(), "gbp".to_string(), 1.3)?;
+            db.drop_table("rates")?;
+            db.rename_table("rates_next", "rates")?;
+            db.rollback()?;
+            assert_eq!(db.get("rates", "gbp")?, &Value::Float(1.25));
+            assert_eq!(db.get("rates_next", "gbp")?, &Value::Float(1.25));
+
+            db.begin()?;
+            db.put("rates_next".to_string(), "gbp".to_string(), 1.3)?;
+            db.drop_table("rates")?;
+            db.rename_table("rates_next", "rates")?;
+            db.commit()?;
+
+            db.copy_table("rates", "scratch")?;
+            db.begin()?;
+            assert_eq!(db.truncate_table("scratch")?, 2);
+            db.rollback()?;
+            assert_eq!(db.keys("scratch", None, None)?.count(), 2);
+            assert_eq!(db.truncate_table("scratch")?, 2);
+        }
+        let db = RustyDb::new(&path)?;
+        assert_eq!(db.list_tables(), vec!["rates", "scratch"]);
+        assert_eq!(db.get("rates", "gbp")?, &Value::Float(1.3));
+        assert!(db.ttl("rates", "eur")?.is_some());
+        assert_eq!(db.keys("scratch", None, None)?.count(), 0);
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_metadata() -> Result<()> {
+        let path = test_db_path("table_metadata");
+        cleanup(&path);
+        let before = now_millis();
+        let created_at = {
+            let mut db = RustyDb::new(&path)?;
+            db.create_table("sessions")?;
+            let info = db.table_info("sessions")?;
+            let created_at = info.created_at.unwrap();
+            assert!(created_at >= before);
+            assert_eq!(info.keys, 0);
+
+            db.put("sessions".to_string(), "s1".to_string(), "abc")?;
+            db.set_table_options(
+                "sessions",
+                TableOptions {
+                    description: Some("login sessions".to_string()),
+                    default_ttl: Some(Duration::from_secs(60)),
+                },
+            )?;
+            //new keys pick up the default ttl, explicit ttls win
+            db.put("sessions".to_string(), "s2".to_string(), "def")?;
+            db.incr_by("sessions", "count", 1)?;
+            db.put_with_ttl(
+                "sessions".to_string(),
+                "s3".to_string(),
+                "ghi",
+                Duration::from_secs(1000),
+            )?;
+            assert_eq!(db.ttl("sessions", "s1")?, None);
+            assert!(db.ttl("sessions", "s2")?.unwrap() <= Duration::from_secs(60));
+            assert!(db.ttl("sessions", "count")?.is_some());
+            assert!(db.ttl("sessions", "s3")?.unwrap() > Duration::from_secs(60));
+
+            let info = db.table_info("sessions")?;
+            assert_eq!(info.keys, 4);
+            assert_eq!(info.approx_bytes, 2 + 3 + 2 + 3 + 5 + 8 + 2 + 3);
+            assert!(info.modified_at.unwrap() >= created_at);
+            created_at
+        };
+        //half from the WAL, then from the snapshot
+        for _ in 0..2 {
+            let mut db = RustyDb::new(&path)?;
+            let info = db.table_info("sessions")?;
+            assert_eq!(info.created_at, Some(created_at));
+            assert_eq!(info.options.description.as_deref(), Some("login sessions"));
+            assert_eq!(info.options.default_ttl, Some(Duration::from_secs(60)));
+            db.checkpoint()?;
+        }
+
+        let mut db = RustyDb::new(&path)?;
+        db.begin()?;
+        db.drop_table("sessions")?;
+        db.rollback()?;
+        assert_eq!(db.table_info("sessions")?.created_at, Some(created_at));
+        db.copy_table("sessions", "copy")?;
+        let copy = db.table_info("copy")?;
+        assert!(copy.created_at.unwrap() >= created_at);
+        assert_eq!(copy.options.default_ttl, Some(Duration::from_secs(60)));
+
+        let parse = |input: &str| crate::command::parse(input).unwrap();
+        db.execute(parse("ALTER copy TTL none DESCRIPTION 'copied sessions'"))?;
+        let described = db.execute(parse("DESCRIBE copy"))?.to_string();
+        assert!(described.starts_with("table: copy\ndescription: copied sessions\ncreated: "));
+        assert!(described.ends_with("keys: 4\napprox size: 28 bytes\ndefault ttl: none"));
+        assert!(matches!(
+            db.table_info("missing"),
+            Err(RustyDbErr::TableNotFound(_))
+        ));
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats() -> Result<()> {
+        let path = test_db_path("stats");
+        cleanup(&path);
+        let mut db = RustyDb::new(&path)?;
+        let parse = |input: &str| crate::command::parse(input).unwrap();
+        db.execute(parse("CREATE t"))?;
+        db.execute(parse("SET t a 1"))?;
+        db.execute(parse("SET t b 2 EX 100"))?;
+        db.execute(parse("GET t a"))?;
+        db.execute(parse("GET t b"))?;
+        db.record_command("PING");
+
+        let stats = db.stats()?;
+        assert_eq!(stats.tables, 1);
+        assert_eq!(stats.keys, 2);
+        assert_eq!(stats.expiring_keys, 1);
+        assert_eq!(stats.operations_since_checkpoint, 3);
+        assert!(stats.wal_bytes > 0);
+        assert_eq!(stats.snapshot_bytes, 0);
+        assert_eq!(stats.total_checkpoints, 0);
+        assert_eq!(stats.commands.get("GET"), Some(&2));
+        assert_eq!(stats.commands.get("SET"), Some(&2));
+        assert_eq!(stats.commands.get("PING"), Some(&1));
+
+        db.checkpoint()?;
+        let stats = db.stats()?;
+        assert_eq!(stats.total_checkpoints, 1);
+        assert_eq!(stats.checkpoints[0].wal_entries, 3);
+        assert_eq!(stats.checkpoints[0].snapshot_bytes, stats.snapshot_bytes);
+        assert!(stats.snapshot_bytes > 0);
+        assert_eq!(stats.operations_since_checkpoint, 0);
+
+        let info = db.execute(parse("INFO"))?.to_string();
+        assert!(info.contains("\ntables: 1\nkeys: 2\n"));
+        assert!(info.contains("\ncommands:\n  CREATE: 1\n  GET: 2\n  INFO: 1\n"));
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoint_policy() -> Result<()> {
+        let path = test_db_path("checkpoint_policy");
+        cleanup(&path);
+        let put = |db: &mut RustyDb, key: &str| db.put("t".to_string(), key.to_string(), "v");
+        let mut db = RustyDb::builder(&path)
+            .checkpoint_policy(CheckpointPolicy::manual())
+            .open()?;
+        db.create_table("t")?;
+        for i in 0..50 {
+            put(&mut db, &i.to_string())?;
+        }
+        assert_eq!(db.operations_since_checkpoint(), 51);
+        assert_eq!(
+            db.execute(Command::Checkpoint)?.to_string(),
+            "Checkpointed 51 WAL entries"
+        );
+        assert_eq!(db.operations_since_checkpoint(), 0);
+
+        let wal_len = fs::metadata(db.wal_path()).unwrap().len();
+        assert_eq!(wal_len, WAL_HEADER_LEN as u64);
+        db.set_checkpoint_policy(CheckpointPolicy {
+            max_wal_bytes: Some(200),
+            ..CheckpointPolicy::manual()
+        });
+        put(&mut db, "a")?;
+        assert_eq!(db.operations_since_checkpoint(), 1);
+        while db.operations_since_checkpoint() > 0 {
+            put(&mut db, "a")?;
+            assert!(fs::metadata(db.wal_path()).unwrap().len() < 200);
+        }
+
+        db.set_checkpoint_policy(CheckpointPolicy {
+            max_interval: Some(Duration::from_millis(20)),
+            ..CheckpointPolicy::manual()
+        });
+        assert!(!db.checkpoint_if_due()?);
+        put(&mut db, "a")?;
+        assert_eq!(db.operations_since_checkpoint(), 1);
+        thread::sleep(Duration::from_millis(30));
+        assert!(db.checkpoint_if_due()?);
+        assert_eq!(db.operations_since_checkpoint(), 0);
+
+        //a foreground checkpoint the policy ran shows up in stats when it fails
+        put(&mut db, "a")?;
+        thread::sleep(Duration::from_millis(30));
+        db.crash_before = Some(CheckpointStep::WriteSnapshot);
+        assert!(db.checkpoint_if_due().is_err());
+        assert!(db.stats()?.last_checkpoint_error.is_some());
+        db.crash_before = None;
+        assert!(db.checkpoint_if_due()?);
+        assert_eq!(db.stats()?.last_checkpoint_error, None);
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_survives_failed_checkpoint() -> Result<()> {
+        let path = test_db_path("write_failed_checkpoint");
+        cleanup(&path);
+        {
+            let mut db = RustyDb::builder(&path)
+                .checkpoint_policy(CheckpointPolicy {
+                    max_entries: Some(1),
+                    ..CheckpointPolicy::manual()
+                })
+                .open()?;
+            db.create_table("t")?;
+            db.crash_before = Some(CheckpointStep::WriteSnapshot);
+            //the checkpoint this triggers fails, the write itself went through
+            assert_eq!(db.incr_by("t", "hits", 1)?, 1);
+            assert!(db.stats()?.last_checkpoint_error.is_some());
+            assert_eq!(db.incr_by("t", "hits", 1)?, 2);
+            db.begin()?;
+            db.put("t".to_string(), "k".to_string(), "v")?;
+            assert_eq!(db.commit()?, 1);
+        }
+        let db = RustyDb::new(&path)?;
+        assert_eq!(db.get("t", "hits")?, &Value::Int(2));
+        assert_eq!(db.get("t", "k")?, &Value::from("v"));
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_background_checkpoint() -> Result<()> {
+        let path = test_db_path("background_checkpoint");
+        cleanup(&path);
+        let policy = CheckpointPolicy {
+            max_entries: Some(3),
+            background: true,
+            ..CheckpointPolicy::default()
+        };
+        {
+            let mut db = RustyDb::builder(&path).checkpoint_policy(policy).open()?;
+            db.create_table("t")?;
+            for i in 0..6 {
+                db.put("t".to_string(), i.to_string(), i)?;
+            }
+            //the fourth entry started a checkpoint, the rest went to a fresh WAL
+            assert_eq!(db.operations_since_checkpoint(), 3);
+            db.wait_for_checkpoint()?;
+            assert!(!Path::new(&db.old_wal_path()).exists());
+            let stats = db.stats()?;
+            assert_eq!(stats.total_checkpoints, 1);
+            assert_eq!(stats.checkpoints[0].wal_entries, 4);
+        }
+        let db = RustyDb::new(&path)?;
+        assert_eq!(db.last_replay().unwrap().entries, 3);
+        assert_eq!(db.get("t", "5")?, &Value::Int(5));
+
+        //a checkpoint that dies part way leaves the old WAL for the next open to replay
+        for step in [CheckpointStep::RenameSnapshot, CheckpointStep::SyncDir] {
+            cleanup(&path);
+            {
+                let mut db = RustyDb::builder(&path).checkpoint_policy(policy).open()?;
+                db.crash_before = Some(step);
+                db.create_table("t")?;
+                for i in 0..6 {
+                    db.put("t".to_string(), i.to_string(), i)?;
+                }
+                //later writes may have collected the failure already
+                db.wait_for_checkpoint().ok();
+                assert!(db.stats()?.last_checkpoint_error.is_some());
+                assert!(Path::new(&db.old_wal_path()).exists());
+            }
+            let db = RustyDb::new(&path)?;
+            //once the snapshot is renamed into place it holds the old WAL, which is skipped
+            let replayed = if step == CheckpointStep::SyncDir {
+                3
+            } else {
+                7
+            };
+            assert_eq!(db.last_replay().unwrap().entries, replayed, "{:?}", step);
+            assert!(!Path::new(&db.old_wal_path()).exists());
+            for i in 0..6 {
+                assert_eq!(db.get("t", &i.to_string())?, &Value::Int(i), "{:?}", step);
+            }
+        }
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact() -> Result<()> {
+        let path = test_db_path("compact");
+        cleanup(&path);
+        let mut db = RustyDb::new(&path)?;
+        db.create_table("t")?;
+        db.put_with_ttl("t".to_string(), "gone".to_string(), "v", Duration::ZERO)?;
+        db.put("t".to_string(), "kept".to_string(), "v")?;
+        assert_eq!(
+            db.execute(Command::Compact)?.to_string(),
+            "Compacted, 1 expired keys removed"
+        );
+        assert_eq!(db.operations_since_checkpoint(), 0);
+        let db = RustyDb::new(&path)?;
+        assert_eq!(db.tables["t"].len(), 1);
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_results() -> Result<()> {
+        let path = test_db_path("execute_results");
+        cleanup(&path);
+        let mut db = RustyDb::new(&path)?;
+        let mut run = |input: &str| db.execute(crate::command::parse(input).unwrap());
+        assert_eq!(run("CREATE t")?, QueryResult::ack("Created table t"));
+        run("MSET t a 1 b \"two\"")?;
+        assert_eq!(run("GET t a")?, QueryResult::Value(Value::Int(1)));
+        assert_eq!(
+            run("MGET t b c")?,
+            QueryResult::Values(vec![
+                ("b".to_string(), Some(Value::from("two"))),
+                ("c".to_string(), None),
+            ])
+        );
+        assert_eq!(
+            run("SCAN t FROM b")?,
+            QueryResult::Rows {
+                rows: vec![("b".to_string(), Value::from("two"))],
+                cursor: None,
+            }
+        );
+        assert_eq!(
+            run("KEYS t * LIMIT 1")?,
+            QueryResult::Keys {
+                keys: vec!["a".to_string()],
+                more: true,
+            }
+        );
+        assert_eq!(run("COUNT t")?, QueryResult::Count(2));
+        assert_eq!(
+            run("SETNX t a 2")?,
+            QueryResult::NotApplied("Not written".to_string())
+        );
+        assert_eq!(run("LIST")?, QueryResult::Tables(vec!["t".to_string()]));
+        assert!(matches!(run("DESCRIBE t")?, QueryResult::Table(info) if info.keys == 2));
+        cleanup(&path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sql() -> Result<()> {
+        let path = test_db_path("sql");
+        cleanup(&path);
+        let mut db = RustyDb::new(&path)?;
+        db.create_table("users")?;
+        let mut run = |input: &str| db.execute(crate::command::parse(input).unwrap());
+        assert_eq!(
+            run("INSERT INTO users VALUES ('alice', 30), ('adam', 41), ('bob', 25)")?,
+            QueryResult::ack("Inserted 3 rows")
+        );
+        assert!(matches!(
+            run("INSERT INTO users VALUES ('carol', 1), ('bob', 2)"),
+            Err(RustyDbErr::InvalidQuery(_))
+        ));
+        assert_eq!(
+            run("SELECT key, value FROM users WHERE key LIKE 'a%' LIMIT 10")?,
+            QueryResult::Rows {
+                rows: vec![
+                    ("adam".to_string(), Value::Int(41)),
+                    ("alice".to_string(), Value::Int(30)),
+                ],
+                cursor: None,
+            }
+        );
+        assert_eq!(
+            run("SELECT key FROM users WHERE value < 35")?,
+            QueryResult::Keys {
+                keys: vec!["alice".to_string(), "bob".to_string()],
+                more: false,
+            }
+        );
+        assert_eq!(run("SELECT COUNT(*) FROM users")?, QueryResult::Count(3));
+
+        run("EXPIRE users bob 100")?;
+        assert_eq!(
+            run("UPDATE users SET value = 'young' WHERE value <= 30")?,
+            QueryResult::ack("Updated 2 rows")
+        );
+        assert_eq!(
+            run("SELECT value FROM users WHERE key >= 'alice'")?,
+            QueryResult::Column(vec![Value::from("young"), Value::from("young")])
+        );
+        assert_ne!(run("TTL users bob")?, QueryResult::Value(Value::Int(-1)));
+
+        assert_eq!(
+            run("DELETE FROM users WHERE key != 'adam'")?,
+            QueryResult::ack("Deleted 2 rows")
+        );
+        assert_eq!(run("SELECT * FROM users")?.to_string(), "adam: 41");
+        assert!(matches!(
+            run("SELECT * FROM missing"),
+            Err(RustyDbErr::TableNotFound(_))
+        ));
+        assert_eq!(db.stats()?.commands.get("SELECT"), Some(&6));
+
+        //writes are logged like any other
+        drop(db);
+        let db = RustyDb::new(&path)?;
+        assert_eq!(db.get("users", "adam")?, &Value::Int(41));
+        assert!(db.get("users", "bob").is_err());
+        cleanup(&path);
+        Ok(())
+    }
 }
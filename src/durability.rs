@@ -0,0 +1,20 @@
+use std::time::Duration;
+
+///How hard `RustyDb` works to get WAL records onto the disk before a write returns
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Durability {
+    ///fsync after every record, nothing acknowledged is ever lost
+    #[default]
+    Always,
+    ///fsync once `max_entries` records are pending or `interval` has passed since the
+    ///last sync, whichever comes first. A power cut can lose the unsynced records.
+    ///With no writes coming the interval is only noticed by
+    ///[`crate::RustyDb::sync_wal_if_due`], which the server's sweeper calls every
+    ///second and the REPL before each line.
+    GroupCommit {
+        interval: Duration,
+        max_entries: usize,
+    },
+    ///Never fsync, leave it to the OS. Survives a process crash but not a power cut.
+    OsBuffered,
+}
@@ -1,47 +1,236 @@
-use std::fmt::Display;
+use std::{error::Error, fmt::Display, ops::Range};
 
-#[derive(Debug, PartialEq, PartialOrd)]
+#[derive(Debug)]
 pub enum RustyDbErr {
     KeyNotFound(String),
-    IoError(String),
-    SerializationError(String),
+    ///A file or socket operation failed, `context` says what we were doing
+    Io {
+        context: String,
+        source: std::io::Error,
+    },
+    ///Encoding or decoding the snapshot or the WAL failed. `source` is the codec's
+    ///error, `None` when we rejected the data ourselves, eg an unknown version
+    Serialization {
+        context: String,
+        source: Option<Box<dyn Error + Send + Sync>>,
+    },
     InvalidQuery(String),
     TableNotFound(String),
     TableExists(String),
+    TransactionError(String),
+    ///The stored value has the wrong type for the operation
+    WrongType(String),
+}
+
+impl RustyDbErr {
+    pub fn io(context: impl Into<String>, source: std::io::Error) -> Self {
+        RustyDbErr::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    pub fn serialization(
+        context: impl Into<String>,
+        source: impl Error + Send + Sync + 'static,
+    ) -> Self {
+        RustyDbErr::Serialization {
+            context: context.into(),
+            source: Some(Box::new(source)),
+        }
+    }
+
+    ///Stable name of the kind of error, for clients to match on instead of the
+    ///message. The server sends it as the first word of an error reply.
+    pub fn code(&self) -> &'static str {
+        match self {
+            RustyDbErr::KeyNotFound(_) => "KEY_NOT_FOUND",
+            RustyDbErr::Io { .. } => "IO_ERROR",
+            RustyDbErr::Serialization { .. } => "SERIALIZATION_ERROR",
+            RustyDbErr::InvalidQuery(_) => "INVALID_QUERY",
+            RustyDbErr::TableNotFound(_) => "TABLE_NOT_FOUND",
+            RustyDbErr::TableExists(_) => "TABLE_EXISTS",
+            RustyDbErr::TransactionError(_) => "TRANSACTION_ERROR",
+            //same as redis, so its clients recognise it
+            RustyDbErr::WrongType(_) => "WRONGTYPE",
+        }
+    }
 }
 
 impl Display for RustyDbErr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             RustyDbErr::KeyNotFound(key) => write!(f, "Key not found: {}", key),
-            RustyDbErr::IoError(err_msg) => {
-                write!(f, "IO error - failed to save to disk {}", err_msg)
-            }
-            RustyDbErr::SerializationError(err_msg) => {
-                write!(f, "Serialization Error: failed to encode data: {}", err_msg)
-            }
+            RustyDbErr::Io { context, source } => write!(f, "IO error {}: {}", context, source),
+            RustyDbErr::Serialization {
+                context,
+                source: Some(source),
+            } => write!(f, "Serialization error {}: {}", context, source),
+            RustyDbErr::Serialization {
+                context,
+                source: None,
+            } => write!(f, "Serialization error: {}", context),
             RustyDbErr::InvalidQuery(err_msg) => write!(f, "Invalid Query Error: {}", err_msg),
             RustyDbErr::TableNotFound(err_msg) => write!(f, "Table not found: {}", err_msg),
             RustyDbErr::TableExists(err_msg) => write!(f, "Table Exists: {}", err_msg),
+            RustyDbErr::TransactionError(err_msg) => write!(f, "Transaction Error: {}", err_msg),
+            RustyDbErr::WrongType(err_msg) => write!(f, "Wrong Type: {}", err_msg),
         }
     }
 }
 
-impl std::error::Error for RustyDbErr {}
+impl Error for RustyDbErr {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RustyDbErr::Io { source, .. } => Some(source),
+            RustyDbErr::Serialization {
+                source: Some(source),
+                ..
+            } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
 
-#[derive(Debug, PartialEq)]
-pub enum ParseError {
-    InvalidCommand(String),
-    WrongNumberOfArguments(String),
+///IO errors are equal if they happened doing the same thing for the same reason,
+///serialization errors if their messages match
+impl PartialEq for RustyDbErr {
+    fn eq(&self, other: &Self) -> bool {
+        use RustyDbErr::*;
+        match (self, other) {
+            (KeyNotFound(a), KeyNotFound(b))
+            | (InvalidQuery(a), InvalidQuery(b))
+            | (TableNotFound(a), TableNotFound(b))
+            | (TableExists(a), TableExists(b))
+            | (TransactionError(a), TransactionError(b))
+            | (WrongType(a), WrongType(b)) => a == b,
+            (
+                Io { context, source },
+                Io {
+                    context: other_context,
+                    source: other_source,
+                },
+            ) => context == other_context && source.kind() == other_source.kind(),
+            (Serialization { .. }, Serialization { .. }) => self.to_string() == other.to_string(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    InvalidCommand,
+    WrongNumberOfArguments,
+    InvalidToken,
+}
+
+///Why a command couldn't be parsed, and where.
+///
+///`token` is the index of the offending token, one past the last token when an
+///argument is missing. `span` is the same place as a byte range of the input line,
+///known when the command was parsed from text with [`crate::parse`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub message: String,
+    pub token: Option<usize>,
+    pub span: Option<Range<usize>>,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, message: impl Into<String>) -> Self {
+        ParseError {
+            kind,
+            message: message.into(),
+            token: None,
+            span: None,
+        }
+    }
+
+    pub fn invalid_command(message: impl Into<String>) -> Self {
+        ParseError::new(ParseErrorKind::InvalidCommand, message)
+    }
+
+    pub fn wrong_arguments(message: impl Into<String>) -> Self {
+        ParseError::new(ParseErrorKind::WrongNumberOfArguments, message)
+    }
+
+    pub fn invalid_token(message: impl Into<String>) -> Self {
+        ParseError::new(ParseErrorKind::InvalidToken, message)
+    }
+
+    ///Blame the token at `index`, unless a more specific token was blamed already
+    pub fn at_token(mut self, index: usize) -> Self {
+        self.token.get_or_insert(index);
+        self
+    }
+
+    ///Move the blamed token along by `by`, for errors found in a slice of the tokens
+    pub fn shifted(mut self, by: usize) -> Self {
+        self.token = self.token.map(|token| token + by);
+        self
+    }
+
+    ///Blame the bytes `span` of the input, unless a span was set already
+    pub fn at_span(mut self, span: Range<usize>) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+
+    ///Stable name of the kind of error, see [`RustyDbErr::code`]
+    pub fn code(&self) -> &'static str {
+        match self.kind {
+            ParseErrorKind::InvalidCommand => "INVALID_COMMAND",
+            ParseErrorKind::WrongNumberOfArguments => "WRONG_ARGUMENTS",
+            ParseErrorKind::InvalidToken => "INVALID_TOKEN",
+        }
+    }
 }
 
 impl std::fmt::Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            ParseError::InvalidCommand(msg) => write!(f, "Invalid Command: {}", msg),
-            ParseError::WrongNumberOfArguments(msg) => {
-                write!(f, "Wrong number of arguments: {}", msg)
+        match self.kind {
+            ParseErrorKind::InvalidCommand => write!(f, "Invalid Command: {}", self.message),
+            ParseErrorKind::WrongNumberOfArguments => {
+                write!(f, "Wrong number of arguments: {}", self.message)
             }
+            ParseErrorKind::InvalidToken => write!(f, "Invalid token: {}", self.message),
         }
     }
 }
+
+impl Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_source() {
+        let missing = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        let err = RustyDbErr::io("reading snapshot db.bin", missing);
+        assert_eq!(err.code(), "IO_ERROR");
+        assert_eq!(
+            err.to_string(),
+            "IO error reading snapshot db.bin: no such file"
+        );
+        let source = err.source().expect("io errors keep their source");
+        assert_eq!(source.to_string(), "no such file");
+
+        let err = RustyDbErr::Serialization {
+            context: "Unsupported WAL version 9".to_string(),
+            source: None,
+        };
+        assert!(err.source().is_none());
+        assert_eq!(err.code(), "SERIALIZATION_ERROR");
+        assert_eq!(RustyDbErr::WrongType("x".to_string()).code(), "WRONGTYPE");
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let err = ParseError::invalid_token("bad").at_token(2).at_token(0);
+        assert_eq!(err.token, Some(2));
+        assert_eq!(err.shifted(3).token, Some(5));
+        assert_eq!(ParseError::wrong_arguments("x").code(), "WRONG_ARGUMENTS");
+    }
+}
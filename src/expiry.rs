@@ -0,0 +1,50 @@
+use std::{
+    sync::{Mutex, PoisonError, Weak},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::db::RustyDb;
+
+///How often the server's sweeper clears out expired keys
+pub const DEFAULT_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+///Expiry times are wall clock unix millis so they mean the same thing after a restart
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+///Unix millis `ttl` from now, saturating for TTLs too long to count in millis
+pub fn deadline(ttl: Duration) -> u64 {
+    let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+    now_millis().saturating_add(ttl)
+}
+
+///Time left until `expires_at`, zero once it has passed
+pub fn remaining(expires_at: u64) -> Duration {
+    Duration::from_millis(expires_at.saturating_sub(now_millis()))
+}
+
+///Periodically remove expired keys so they don't sit in memory until someone reads them,
+///and run the group commit syncs and time based checkpoints that no write has triggered.
+///The thread stops once the database has been dropped.
+pub fn spawn_sweeper(db: Weak<Mutex<RustyDb>>, interval: Duration) -> JoinHandle<()> {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+            let Some(db) = db.upgrade() else {
+                return;
+            };
+            let mut db = db.lock().unwrap_or_else(PoisonError::into_inner);
+            db.sweep_expired();
+            if let Err(why) = db.sync_wal_if_due() {
+                eprintln!("WAL sync failed: {}", why);
+            }
+            if let Err(why) = db.checkpoint_if_due() {
+                eprintln!("Checkpoint failed: {}", why);
+            }
+        }
+    })
+}
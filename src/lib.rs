@@ -0,0 +1,45 @@
+//! RustyDB - a tiny table/key/value store with a write ahead log.
+//!
+//! The REPL binary is a thin wrapper over this crate; anything it can do,
+//! an embedding application can do through [`RustyDb`] and [`parse`].
+//! [`RustyDb::execute`] hands back a [`QueryResult`] for the caller to use as data
+//! or print with [`render`]. [`parse`] also takes a small SQL subset, see [`sql`].
+
+pub mod checkpoint;
+pub mod command;
+pub mod cursor;
+pub mod db;
+pub mod durability;
+pub mod err_types;
+pub mod expiry;
+pub mod pattern;
+pub mod query_result;
+pub mod render;
+pub mod resp;
+pub mod server;
+pub mod sql;
+pub mod stats;
+pub mod table_meta;
+pub mod tokenizer;
+pub mod value;
+pub mod wal;
+
+pub use checkpoint::CheckpointPolicy;
+pub use command::{Command, PutCondition, parse, parse_tokens};
+pub use cursor::Cursor;
+pub use db::{RustyDb, RustyDbBuilder, ScanPage, Table};
+pub use durability::Durability;
+pub use err_types::{ParseError, ParseErrorKind, RustyDbErr};
+pub use pattern::KeyPattern;
+pub use query_result::QueryResult;
+pub use render::{OutputFormat, render};
+pub use server::Server;
+pub use sql::Statement;
+pub use stats::{CheckpointInfo, Stats};
+pub use table_meta::{TableInfo, TableOptions};
+pub use tokenizer::Token;
+pub use value::Value;
+pub use wal::{WalEntry, WalReplay};
+
+///Result type used throughout the public API
+pub type Result<T> = std::result::Result<T, RustyDbErr>;
@@ -1,56 +1,431 @@
-use std::io::Write;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, IsTerminal},
+    ops::Range,
+    process::ExitCode,
+};
 
-use crate::{command::parse, db::RustyDb};
+use colored::Colorize;
+use rusty_db::{OutputFormat, RustyDb, Server, parse, render};
+use rustyline::{Editor, error::ReadlineError, history::DefaultHistory};
 
-mod command;
-mod db;
-mod err_types;
-mod wal;
+use crate::repl::ReplHelper;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+mod repl;
+
+///Where `--serve` listens unless given an address
+const DEFAULT_SERVE_ADDR: &str = "127.0.0.1:6379";
+
+///Database file used when no path is given
+const DEFAULT_DB_PATH: &str = ".rusty.db";
+
+///What the command line asked for
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    db_path: Option<String>,
+    ///address to serve on, `--serve`
+    serve: Option<String>,
+    ///`-c` commands, run in order
+    commands: Vec<String>,
+    ///`-f` script, `-` for stdin
+    script: Option<String>,
+    ///no banner, prompt or goodbye
+    quiet: bool,
+    format: OutputFormat,
+    help: bool,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(why) => {
+            eprintln!("{}", why);
+            eprintln!("Try 'rusty_db --help'");
+            return ExitCode::from(2);
+        }
+    };
+    if options.help {
+        print_usage();
+        return ExitCode::SUCCESS;
+    }
+    match run(options) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(why) => {
+            eprintln!("ERROR: {}", why);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => options.help = true,
+            "-q" | "--quiet" => options.quiet = true,
+            "--format" => {
+                let format = args.next().ok_or("--format needs a format")?;
+                options.format = format.parse().map_err(|why| format!("{}", why))?;
+            }
+            "-c" | "--command" => {
+                let command = args.next().ok_or("-c needs a command")?;
+                options.commands.push(command);
+            }
+            "-f" | "--file" => {
+                let script = args.next().ok_or("-f needs a script file")?;
+                options.script = Some(script);
+            }
+            "--serve" => {
+                //the address is optional
+                let addr = args.next_if(|next| !next.starts_with('-'));
+                options.serve = Some(addr.unwrap_or_else(|| DEFAULT_SERVE_ADDR.to_string()));
+            }
+            flag if flag.starts_with('-') && flag != "-" => {
+                return Err(format!("Unknown option {}", flag));
+            }
+            _ if options.db_path.is_none() => options.db_path = Some(arg),
+            _ => return Err(format!("Unexpected argument {}", arg)),
+        }
+    }
+    if options.serve.is_some() && (!options.commands.is_empty() || options.script.is_some()) {
+        return Err("--serve can't be combined with -c or -f".to_string());
+    }
+    Ok(options)
+}
+
+///Returns whether every command worked
+fn run(options: Options) -> Result<bool, Box<dyn std::error::Error>> {
+    let db_path = options.db_path.as_deref().unwrap_or(DEFAULT_DB_PATH);
+    if let Some(addr) = &options.serve {
+        let server = Server::bind(RustyDb::new(db_path)?, addr)?;
+        println!("RustyDB listening on {}", server.local_addr()?);
+        server.run()?;
+        return Ok(true);
+    }
+
+    //escape codes would only get in the way of whatever reads our output
+    if !std::io::stdout().is_terminal() {
+        colored::control::set_override(false);
+    }
+    let mut format = options.format;
+    let mut db = RustyDb::new(db_path)?;
+    if let Some(replay) = db.last_replay()
+        && let Some(why) = &replay.corruption
+    {
+        eprintln!(
+            "WARNING: WAL recovery stopped early ({}), discarded {} bytes",
+            why, replay.discarded_bytes
+        );
+    }
+
+    if !options.commands.is_empty() || options.script.is_some() {
+        let mut ok = true;
+        for command in &options.commands {
+            match run_line(&mut db, &mut format, command) {
+                Line::Done(worked) => ok &= worked,
+                Line::Exit => return Ok(ok),
+            }
+        }
+        if let Some(script) = &options.script {
+            let worked = match script.as_str() {
+                "-" => run_lines(&mut db, &mut format, std::io::stdin().lock())?,
+                path => run_lines(&mut db, &mut format, BufReader::new(File::open(path)?))?,
+            };
+            ok &= worked;
+        }
+        return Ok(ok);
+    }
+
+    //piped input gets the same treatment as a script
+    if !std::io::stdin().is_terminal() || options.quiet {
+        return run_lines(&mut db, &mut format, std::io::stdin().lock());
+    }
     println!("RustyDB Sea Ally");
     println!("Type 'help' for commands, 'exit' to quit\n");
+    repl(&mut db, &mut format, &format!("{}.history", db_path))?;
+    println!("See you later, Space Cowboy");
+    //a typo at the prompt isn't a failure of the session
+    Ok(true)
+}
 
-    let mut db = RustyDb::new(".rusty.db")?;
+///Prompt for commands with line editing and tab completion until EOF or exit,
+///keeping history in `history_path`
+fn repl(
+    db: &mut RustyDb,
+    format: &mut OutputFormat,
+    history_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(ReplHelper::default()));
+    //there's no history before the first session
+    editor.load_history(history_path).ok();
     loop {
         //main cli loop
-        print!("rustydb>> ");
-        std::io::stdout().flush()?;
-
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        let input = input.trim();
-
-        //check for commands that aren't sql
-        match input.to_lowercase().as_str() {
-            "exit" | "quit" | "q" => {
-                println!("See you later, Space Cowboy");
-                break;
-            }
-            "help" => {
-                print_help();
-                continue;
-            }
-            _ => match parse(&input) {
-                Ok(cmd) => match db.execute(cmd) {
-                    Ok(result) => println!("{}", result),
-                    Err(why) => eprintln!("ERROR: {}", why),
-                },
-                Err(why) => eprintln!("Parser error: {}", why),
-            },
+        if let Some(helper) = editor.helper_mut() {
+            helper.tables = db.list_tables();
+        }
+        let line = match editor.readline("rustydb>> ") {
+            Ok(line) => line,
+            //ctrl-c drops the line, ctrl-d leaves
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(why) => return Err(why.into()),
+        };
+        let line = line.trim();
+        if !line.is_empty() {
+            editor.add_history_entry(line)?;
+        }
+        if let Line::Exit = run_line(db, format, line) {
+            break;
         }
     }
+    editor.save_history(history_path)?;
     Ok(())
 }
 
+///How running one line went
+enum Line {
+    ///whether the command worked
+    Done(bool),
+    Exit,
+}
+
+///Run each line until EOF or exit, returns whether every command worked
+fn run_lines(
+    db: &mut RustyDb,
+    format: &mut OutputFormat,
+    mut input: impl BufRead,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut ok = true;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(ok);
+        }
+        match run_line(db, format, line.trim()) {
+            Line::Done(worked) => ok &= worked,
+            Line::Exit => return Ok(ok),
+        }
+    }
+}
+
+fn run_line(db: &mut RustyDb, format: &mut OutputFormat, input: &str) -> Line {
+    db.sweep_expired();
+    if let Err(why) = db.sync_wal_if_due() {
+        eprintln!("{} WAL sync failed: {}", "ERROR:".red().bold(), why);
+    }
+    if let Err(why) = db.checkpoint_if_due() {
+        eprintln!("{} checkpoint failed: {}", "ERROR:".red().bold(), why);
+    }
+
+    //blank lines and # comments, so scripts can be laid out
+    if input.is_empty() || input.starts_with('#') {
+        return Line::Done(true);
+    }
+    if let Some(meta) = input.strip_prefix('\\') {
+        return Line::Done(meta_command(format, meta));
+    }
+    //check for commands that aren't sql
+    match input.to_lowercase().as_str() {
+        "exit" | "quit" | "q" => Line::Exit,
+        "help" => {
+            print_help();
+            Line::Done(true)
+        }
+        _ => match parse(input) {
+            Ok(cmd) => match db.execute(cmd) {
+                Ok(result) => {
+                    println!("{}", render(&result, *format));
+                    Line::Done(true)
+                }
+                Err(why) => {
+                    eprintln!("{} {}", "ERROR:".red().bold(), why);
+                    Line::Done(false)
+                }
+            },
+            Err(why) => {
+                eprintln!("{} {}", "Parser error:".red().bold(), why);
+                if let Some(span) = &why.span {
+                    eprintln!("  {}", input);
+                    eprintln!("  {}", underline(input, span).red().bold());
+                }
+                Line::Done(false)
+            }
+        },
+    }
+}
+
+///Carets under the `span` bytes of `input`, at least one so a missing argument
+///shows up just past the end of the line
+fn underline(input: &str, span: &Range<usize>) -> String {
+    let indent = input[..span.start].chars().count();
+    let width = input[span.clone()].chars().count().max(1);
+    format!("{}{}", " ".repeat(indent), "^".repeat(width))
+}
+
+///Backslash commands change how the REPL behaves rather than touching the database.
+///Returns whether it worked.
+fn meta_command(format: &mut OutputFormat, meta: &str) -> bool {
+    let words: Vec<&str> = meta.split_whitespace().collect();
+    match words.as_slice() {
+        ["format"] => println!("Output format is {}", format),
+        ["format", name] => match name.parse() {
+            Ok(new) => {
+                *format = new;
+                println!("Output format is {}", format);
+            }
+            Err(why) => {
+                eprintln!("{} {}", "ERROR:".red().bold(), why);
+                return false;
+            }
+        },
+        _ => {
+            eprintln!(
+                "{} Unknown meta-command \\{}, try \\format",
+                "ERROR:".red().bold(),
+                meta
+            );
+            return false;
+        }
+    }
+    true
+}
+
+fn print_usage() {
+    println!("Usage: rusty_db [OPTIONS] [DB_PATH]");
+    println!();
+    println!(
+        "Opens {} unless given DB_PATH. With no -c or -f, commands are read",
+        DEFAULT_DB_PATH
+    );
+    println!("from stdin: a prompt in a terminal, silently when piped.");
+    println!();
+    println!("Options:");
+    println!("  -c, --command <command>    Run a command and exit, may be repeated");
+    println!("  -f, --file <script>        Run the commands in a file and exit, - for stdin");
+    println!("  -q, --quiet                No banner or prompt");
+    println!("      --format <format>      Print results as text, table, json or csv");
+    println!(
+        "      --serve [addr]         Serve redis clients on addr, default {}",
+        DEFAULT_SERVE_ADDR
+    );
+    println!("  -h, --help                 Show this help");
+    println!();
+    println!("Exits with status 1 if any command run non-interactively failed.");
+}
+
 fn print_help() {
     println!("Available commands:");
     println!("  CREATE <table>             - Create a new table");
     println!("  DROP <table>               - Drop a table");
+    println!("  RENAME <table> <new>       - Rename a table");
+    println!("  TRUNCATE <table>           - Delete every key in a table");
+    println!("  COPY <table> <new>         - Copy a table into a new one");
+    println!("  DESCRIBE <table>           - Show a table's metadata");
+    println!("  ALTER <table> [DESCRIPTION <text>|NONE] [TTL <secs>|NONE]");
+    println!("                             - Set a description or a default ttl for new keys");
     println!("  LIST                       - List all tables");
-    println!("  SET <table> <key> <value>  - Set a key-value pair");
+    println!("  INFO                       - Show database statistics");
+    println!("  CHECKPOINT                 - Write a snapshot and empty the WAL");
+    println!("  COMPACT                    - Remove expired keys, then checkpoint");
+    println!("  SET <table> <key> <value> [EX <secs> | PX <ms>] [NX | XX]");
+    println!("                             - Set a key-value pair, optionally expiring");
+    println!("                             - NX only sets a missing key, XX only an existing one");
+    println!("  SETNX <table> <key> <value> - Set a key only if it doesn't exist");
+    println!("  CAS <table> <key> <expected> <new>");
+    println!("                             - Set a key only if it still holds expected");
     println!("  GET <table> <key>          - Get a value by key");
     println!("  DEL <table> <key>          - Delete a key");
+    println!("  MGET <table> <key>...      - Get several keys");
+    println!("  MSET <table> <key> <value>... - Set several keys all-or-nothing");
+    println!("  MDEL <table> <key>...      - Delete several keys all-or-nothing");
+    println!("  INCR / DECR <table> <key>  - Add or subtract 1, a missing key counts as 0");
+    println!("  INCRBY / DECRBY / INCRBYFLOAT <table> <key> <amount>");
+    println!("                             - Add or subtract amount atomically");
+    println!("  EXPIRE <table> <key> <secs> - Expire a key after secs, 0 deletes it");
+    println!("  TTL <table> <key>          - Seconds until a key expires, -1 if never");
+    println!("  PERSIST <table> <key>      - Stop a key from expiring");
+    println!("  SCAN <table> [FROM <key>] [TO <key>] [LIMIT <n>]");
+    println!("                             - List keys in order, FROM inclusive, TO exclusive");
+    println!("  SCAN <table> <cursor> [MATCH <pattern>] [COUNT <n>]");
+    println!("                             - Page through keys, start at cursor 0 and pass");
+    println!("                               back the cursor given until it is 0 again");
+    println!("  PREFIX <table> <prefix> [LIMIT <n>]");
+    println!("                             - List keys starting with prefix, in order");
+    println!("  KEYS <table> [pattern] [LIMIT <n>] [AFTER <key>]");
+    println!(
+        "                             - List keys matching a glob or /regex/, page with AFTER"
+    );
+    println!("  COUNT <table> [pattern]    - Count keys, optionally matching a pattern");
+    println!("  BEGIN / COMMIT / ROLLBACK  - Group writes into one atomic transaction");
+    println!("  SELECT * | key | value | COUNT(*) FROM <table> [WHERE ...] [LIMIT <n>]");
+    println!("  INSERT INTO <table> VALUES ('<key>', <value>), ...");
+    println!("  UPDATE <table> SET value = <value> [WHERE ...]");
+    println!("  DELETE FROM <table> [WHERE ...]");
+    println!("                             - SQL over a table's key and value columns, WHERE");
+    println!("                               takes = != < <= > >= and LIKE joined by AND");
+    println!("  \\format [text|table|json|csv] - Show or change how results are printed");
     println!("  help                       - Show this help");
     println!("  exit                       - Exit the REPL");
+    println!();
+    println!(
+        "Values may be \"quoted\" or 'quoted', and x'..' / b64'..' give hex / base64 literals"
+    );
+    println!("Unquoted 42, 1.5, true, null, [lists] and {{maps}} are stored as typed values");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Result<Options, String> {
+        parse_args(line.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(args(""), Ok(Options::default()));
+        assert_eq!(
+            args("my.db -q -c LIST -c INFO"),
+            Ok(Options {
+                db_path: Some("my.db".to_string()),
+                commands: vec!["LIST".to_string(), "INFO".to_string()],
+                quiet: true,
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            args("-f -").map(|options| options.script),
+            Ok(Some("-".to_string()))
+        );
+        assert_eq!(
+            args("--serve").map(|options| options.serve),
+            Ok(Some(DEFAULT_SERVE_ADDR.to_string()))
+        );
+        assert_eq!(
+            args("--serve 0.0.0.0:7000 -q").map(|options| options.serve),
+            Ok(Some("0.0.0.0:7000".to_string()))
+        );
+        assert!(args("-c").is_err());
+        assert!(args("--verbose").is_err());
+        assert!(args("a.db b.db").is_err());
+        assert!(args("--serve -c LIST").is_err());
+        assert_eq!(
+            args("--format csv").map(|options| options.format),
+            Ok(OutputFormat::Csv)
+        );
+        assert!(args("--format xml").is_err());
+    }
+
+    #[test]
+    fn test_underline() {
+        let input = "SCAN events LIMIT ten";
+        let span = parse(input).unwrap_err().span.unwrap();
+        assert_eq!(underline(input, &span), "                  ^^^");
+        let input = "GET users";
+        let span = parse(input).unwrap_err().span.unwrap();
+        assert_eq!(underline(input, &span), "         ^");
+    }
 }
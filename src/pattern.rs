@@ -0,0 +1,243 @@
+use regex::Regex;
+
+use crate::err_types::ParseError;
+
+///A pattern keys are matched against, a glob like `user:*` or a regex written `/^user:\d+$/`
+#[derive(Debug, Clone)]
+pub struct KeyPattern {
+    source: String,
+    ///text every match starts with, lets lookups skip straight to it in key order
+    prefix: String,
+    kind: PatternKind,
+}
+
+#[derive(Debug, Clone)]
+enum PatternKind {
+    Glob(Vec<GlobToken>),
+    ///matches anywhere in the key unless anchored
+    Regex(Regex),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum GlobToken {
+    Literal(char),
+    ///`?`, any one character
+    Any,
+    ///`*`, any run of characters
+    Star,
+    ///`[a-z]`, or `[!a-z]` when negated
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl KeyPattern {
+    ///Compile a pattern, text between slashes is a regex and anything else a glob.
+    ///Globs support `*`, `?`, `[abc]`, `[a-z]`, `[!abc]` and `\` to escape.
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        if source.len() >= 2 && source.starts_with('/') && source.ends_with('/') {
+            let regex = Regex::new(&source[1..source.len() - 1]).map_err(|e| {
+                ParseError::invalid_token(format!("Invalid regex {}: {}", source, e))
+            })?;
+            return Ok(Self {
+                source: source.to_string(),
+                prefix: String::new(),
+                kind: PatternKind::Regex(regex),
+            });
+        }
+        Ok(Self::from_glob(source, parse_glob(source)?))
+    }
+
+    fn from_glob(source: &str, tokens: Vec<GlobToken>) -> Self {
+        let prefix = tokens
+            .iter()
+            .map_while(|token| match token {
+                GlobToken::Literal(c) => Some(*c),
+                _ => None,
+            })
+            .collect();
+        Self {
+            source: source.to_string(),
+            prefix,
+            kind: PatternKind::Glob(tokens),
+        }
+    }
+
+    ///Compile a SQL `LIKE` pattern, `%` matches any run of characters and `_` any one.
+    ///Everything else, `*` and `?` included, is literal.
+    pub fn like(source: &str) -> Self {
+        let mut tokens = Vec::new();
+        for c in source.chars() {
+            let token = match c {
+                '%' if tokens.last() == Some(&GlobToken::Star) => continue,
+                '%' => GlobToken::Star,
+                '_' => GlobToken::Any,
+                c => GlobToken::Literal(c),
+            };
+            tokens.push(token);
+        }
+        Self::from_glob(source, tokens)
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        match &self.kind {
+            PatternKind::Glob(tokens) => glob_matches(tokens, &key.chars().collect::<Vec<_>>()),
+            PatternKind::Regex(regex) => regex.is_match(key),
+        }
+    }
+
+    ///Every matching key starts with this, empty for regexes
+    pub fn literal_prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+impl PartialEq for KeyPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+fn parse_glob(source: &str) -> Result<Vec<GlobToken>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            //a run of stars matches the same as one
+            '*' if tokens.last() == Some(&GlobToken::Star) => continue,
+            '*' => GlobToken::Star,
+            '?' => GlobToken::Any,
+            '\\' => GlobToken::Literal(chars.next().unwrap_or('\\')),
+            '[' => parse_class(&mut chars, source)?,
+            c => GlobToken::Literal(c),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+///The rest of a `[...]` class, after the opening bracket
+fn parse_class(chars: &mut std::str::Chars, source: &str) -> Result<GlobToken, ParseError> {
+    let negated = matches!(chars.clone().next(), Some('!' | '^'));
+    if negated {
+        chars.next();
+    }
+    let mut ranges = Vec::new();
+    loop {
+        let c = match chars.next() {
+            //a leading `]` is part of the class, not its end
+            Some(']') if !ranges.is_empty() => break,
+            Some('\\') => chars.next().unwrap_or('\\'),
+            Some(c) => c,
+            None => {
+                return Err(ParseError::invalid_token(format!(
+                    "Missing closing ']' in pattern {}",
+                    source
+                )));
+            }
+        };
+        let mut ahead = chars.clone();
+        match (ahead.next(), ahead.next()) {
+            (Some('-'), Some(end)) if end != ']' => {
+                chars.next();
+                chars.next();
+                ranges.push((c, end));
+            }
+            _ => ranges.push((c, c)),
+        }
+    }
+    Ok(GlobToken::Class { negated, ranges })
+}
+
+fn glob_matches(tokens: &[GlobToken], key: &[char]) -> bool {
+    let (mut t, mut k) = (0, 0);
+    //where the last star was, and how much of the key it has eaten so far
+    let mut backtrack = None;
+    while k < key.len() {
+        match tokens.get(t) {
+            Some(GlobToken::Star) => {
+                backtrack = Some((t, k));
+                t += 1;
+                continue;
+            }
+            Some(token) if token_matches(token, key[k]) => {
+                t += 1;
+                k += 1;
+                continue;
+            }
+            _ => {}
+        }
+        //mismatch, let the last star swallow one more character and retry
+        match backtrack {
+            Some((star, eaten)) => {
+                t = star + 1;
+                k = eaten + 1;
+                backtrack = Some((star, eaten + 1));
+            }
+            None => return false,
+        }
+    }
+    tokens[t..].iter().all(|token| *token == GlobToken::Star)
+}
+
+fn token_matches(token: &GlobToken, c: char) -> bool {
+    match token {
+        GlobToken::Literal(literal) => *literal == c,
+        GlobToken::Any => true,
+        GlobToken::Star => false,
+        GlobToken::Class { negated, ranges } => {
+            ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c)) != *negated
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, key: &str) -> bool {
+        KeyPattern::parse(pattern).unwrap().matches(key)
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(matches("user:*", "user:1"));
+        assert!(matches("user:*", "user:"));
+        assert!(!matches("user:*", "order:1"));
+        assert!(matches("*:1*", "order:12"));
+        assert!(matches("a*b*c", "axxbyyc"));
+        assert!(!matches("a*b*c", "axxbyy"));
+        assert!(matches("h?llo", "hello"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[!ae]llo", "hallo"));
+        assert!(matches("id:[0-9][0-9]", "id:42"));
+        assert!(!matches("id:[0-9][0-9]", "id:4x"));
+        assert!(matches(r"what\?", "what?"));
+        assert!(!matches(r"what\?", "whats"));
+        assert!(KeyPattern::parse("h[ae").is_err());
+    }
+
+    #[test]
+    fn test_regex() {
+        assert!(matches(r"/^user:\d+$/", "user:42"));
+        assert!(!matches(r"/^user:\d+$/", "user:42a"));
+        assert!(matches("/ord/", "big-order"));
+        assert!(KeyPattern::parse("/(/").is_err());
+    }
+
+    #[test]
+    fn test_literal_prefix() {
+        assert_eq!(
+            KeyPattern::parse("user:*").unwrap().literal_prefix(),
+            "user:"
+        );
+        assert_eq!(KeyPattern::parse(r"a\*b?").unwrap().literal_prefix(), "a*b");
+        assert_eq!(KeyPattern::parse("/^user/").unwrap().literal_prefix(), "");
+    }
+}
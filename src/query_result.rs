@@ -0,0 +1,110 @@
+use std::fmt::Display;
+
+use crate::{cursor::Cursor, stats::Stats, table_meta::TableInfo, value::Value};
+
+///What running a [`crate::Command`] produced. Displays as the plain text the REPL
+///has always printed, see [`crate::render`] for the other formats.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryResult {
+    ///GET, the counters, TTL in seconds (-1 when the key never expires), and the
+    ///value a DEL removed
+    Value(Value),
+    ///MGET, per key its value or `None` if it doesn't exist
+    Values(Vec<(String, Option<Value>)>),
+    ///SCAN and PREFIX, with the cursor to carry on from for the cursor form of SCAN
+    Rows {
+        rows: Vec<(String, Value)>,
+        cursor: Option<Cursor>,
+    },
+    ///KEYS, `more` when LIMIT cut the list short
+    Keys {
+        keys: Vec<String>,
+        more: bool,
+    },
+    ///A SQL SELECT of just the value column
+    Column(Vec<Value>),
+    Count(usize),
+    ///A write went through
+    Ack(String),
+    ///A conditional write, or a PERSIST, that had nothing to do
+    NotApplied(String),
+    Tables(Vec<String>),
+    Table(TableInfo),
+    Stats(Stats),
+}
+
+impl QueryResult {
+    pub(crate) fn ack(message: impl Into<String>) -> Self {
+        QueryResult::Ack(message.into())
+    }
+
+    pub(crate) fn rows<'a>(
+        rows: impl Iterator<Item = (&'a String, &'a Value)>,
+        cursor: Option<Cursor>,
+    ) -> Self {
+        QueryResult::Rows {
+            rows: rows
+                .map(|(key, val)| (key.to_string(), val.clone()))
+                .collect(),
+            cursor,
+        }
+    }
+}
+
+impl Display for QueryResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryResult::Value(val) => write!(f, "{}", val),
+            QueryResult::Values(vals) => {
+                let lines: Vec<String> = vals
+                    .iter()
+                    .map(|(key, val)| match val {
+                        Some(val) => format!("{}: {}", key, val),
+                        None => format!("{}: (not found)", key),
+                    })
+                    .collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            QueryResult::Rows { rows, cursor } => {
+                if let Some(cursor) = cursor {
+                    writeln!(f, "cursor: {}", cursor)?;
+                }
+                if rows.is_empty() {
+                    return write!(f, "No keys found");
+                }
+                let lines: Vec<String> = rows
+                    .iter()
+                    .map(|(key, val)| format!("{}: {}", key, val))
+                    .collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            QueryResult::Keys { keys, more } => {
+                if keys.is_empty() {
+                    return write!(f, "No keys found");
+                }
+                write!(f, "{}", keys.join("\n"))?;
+                match keys.last() {
+                    Some(last) if *more => write!(
+                        f,
+                        "\n-- more keys, continue with AFTER {}",
+                        Value::from(last.as_str())
+                    ),
+                    _ => Ok(()),
+                }
+            }
+            QueryResult::Column(vals) if vals.is_empty() => write!(f, "No keys found"),
+            QueryResult::Column(vals) => {
+                let lines: Vec<String> = vals.iter().map(Value::to_string).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            QueryResult::Count(count) => write!(f, "{}", count),
+            QueryResult::Ack(message) | QueryResult::NotApplied(message) => {
+                write!(f, "{}", message)
+            }
+            QueryResult::Tables(tables) if tables.is_empty() => write!(f, "No tables found"),
+            QueryResult::Tables(tables) => write!(f, "{}", tables.join("\n")),
+            QueryResult::Table(info) => write!(f, "{}", info),
+            QueryResult::Stats(stats) => write!(f, "{}", stats),
+        }
+    }
+}
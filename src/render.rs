@@ -0,0 +1,591 @@
+use std::{fmt::Display, str::FromStr};
+
+use colored::{ColoredString, Colorize};
+
+use crate::{
+    err_types::ParseError, query_result::QueryResult, stats::Stats, table_meta::TableInfo,
+    value::Value,
+};
+
+///How the REPL prints a [`QueryResult`]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    ///the plain text of [`QueryResult`]'s Display, colored by type
+    #[default]
+    Text,
+    ///aligned columns under a header
+    Table,
+    Json,
+    ///a header row then one row per line, for spreadsheets and scripts
+    Csv,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 4] = [
+        OutputFormat::Text,
+        OutputFormat::Table,
+        OutputFormat::Json,
+        OutputFormat::Csv,
+    ];
+}
+
+impl FromStr for OutputFormat {
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        OutputFormat::ALL
+            .into_iter()
+            .find(|format| format.to_string().eq_ignore_ascii_case(text))
+            .ok_or_else(|| {
+                ParseError::invalid_token(format!(
+                    "Unknown format {}, expected text, table, json or csv",
+                    text
+                ))
+            })
+    }
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            OutputFormat::Text => "text",
+            OutputFormat::Table => "table",
+            OutputFormat::Json => "json",
+            OutputFormat::Csv => "csv",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+///Render a result for printing. Only the text format is colored, and only while
+///`colored` is allowed to, see `colored::control`.
+pub fn render(result: &QueryResult, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Text => text(result),
+        OutputFormat::Table => table(&grid(result)),
+        OutputFormat::Json => json(result),
+        OutputFormat::Csv => csv(&grid(result)),
+    }
+}
+
+fn text(result: &QueryResult) -> String {
+    let entry = |key: &str, val: ColoredString| format!("{}: {}", key.bold(), val);
+    let no_keys = || "No keys found".dimmed().to_string();
+    match result {
+        QueryResult::Value(val) => colored_value(val).to_string(),
+        QueryResult::Values(vals) => vals
+            .iter()
+            .map(|(key, val)| match val {
+                Some(val) => entry(key, colored_value(val)),
+                None => entry(key, "(not found)".dimmed()),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        QueryResult::Rows { rows, cursor } => {
+            let mut lines = Vec::new();
+            if let Some(cursor) = cursor {
+                lines.push(format!("cursor: {}", cursor).dimmed().to_string());
+            }
+            if rows.is_empty() {
+                lines.push(no_keys());
+            }
+            lines.extend(rows.iter().map(|(key, val)| entry(key, colored_value(val))));
+            lines.join("\n")
+        }
+        QueryResult::Keys { keys, .. } if keys.is_empty() => no_keys(),
+        QueryResult::Keys { keys, more } => {
+            let mut lines = keys.clone();
+            if *more && let Some(last) = keys.last() {
+                let hint = format!(
+                    "-- more keys, continue with AFTER {}",
+                    Value::from(last.as_str())
+                );
+                lines.push(hint.dimmed().to_string());
+            }
+            lines.join("\n")
+        }
+        QueryResult::Column(vals) if vals.is_empty() => no_keys(),
+        QueryResult::Column(vals) => vals
+            .iter()
+            .map(|val| colored_value(val).to_string())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        QueryResult::Count(count) => count.to_string().cyan().to_string(),
+        QueryResult::Ack(message) => message.green().to_string(),
+        QueryResult::NotApplied(message) => message.yellow().to_string(),
+        QueryResult::Tables(tables) if tables.is_empty() => "No tables found".dimmed().to_string(),
+        QueryResult::Table(_) | QueryResult::Stats(_) | QueryResult::Tables(_) => {
+            result.to_string()
+        }
+    }
+}
+
+fn colored_value(val: &Value) -> ColoredString {
+    let text = val.to_string();
+    match val {
+        Value::Null => text.dimmed(),
+        Value::Bool(_) => text.yellow(),
+        Value::Int(_) | Value::Float(_) => text.cyan(),
+        Value::Str(_) => text.green(),
+        Value::Bytes(_) => text.magenta(),
+        Value::List(_) | Value::Map(_) => text.normal(),
+    }
+}
+
+///A result laid out as columns, shared by the table and CSV formats
+struct Grid {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<Cell>>,
+}
+
+///A value keeps its type so CSV can write strings bare, everything else is text
+enum Cell {
+    Value(Value),
+    Text(String),
+}
+
+impl Cell {
+    fn text(text: impl Display) -> Self {
+        Cell::Text(text.to_string())
+    }
+
+    ///How the table format shows it, strings quoted like everywhere else in the REPL
+    fn display(&self) -> String {
+        match self {
+            Cell::Value(val) => val.to_string(),
+            Cell::Text(text) => text.to_string(),
+        }
+    }
+
+    ///How CSV writes it, already quoted by CSV's own rules when needed
+    fn raw(&self) -> String {
+        match self {
+            Cell::Value(Value::Str(s)) | Cell::Text(s) => s.to_string(),
+            Cell::Value(val) => val.to_string(),
+        }
+    }
+}
+
+fn grid(result: &QueryResult) -> Grid {
+    let (headers, rows): (Vec<&str>, Vec<Vec<Cell>>) = match result {
+        QueryResult::Value(val) => (vec!["value"], vec![vec![Cell::Value(val.clone())]]),
+        QueryResult::Values(vals) => (
+            vec!["key", "value", "found"],
+            vals.iter()
+                .map(|(key, val)| {
+                    vec![
+                        Cell::text(key),
+                        Cell::Value(val.clone().unwrap_or(Value::Null)),
+                        Cell::text(val.is_some()),
+                    ]
+                })
+                .collect(),
+        ),
+        QueryResult::Rows { rows, .. } => (
+            vec!["key", "value"],
+            rows.iter()
+                .map(|(key, val)| vec![Cell::text(key), Cell::Value(val.clone())])
+                .collect(),
+        ),
+        QueryResult::Keys { keys, .. } => (
+            vec!["key"],
+            keys.iter().map(|key| vec![Cell::text(key)]).collect(),
+        ),
+        QueryResult::Column(vals) => (
+            vec!["value"],
+            vals.iter()
+                .map(|val| vec![Cell::Value(val.clone())])
+                .collect(),
+        ),
+        QueryResult::Count(count) => (vec!["count"], vec![vec![Cell::text(count)]]),
+        QueryResult::Ack(message) | QueryResult::NotApplied(message) => (
+            vec!["ok", "message"],
+            vec![vec![
+                Cell::text(matches!(result, QueryResult::Ack(_))),
+                Cell::text(message),
+            ]],
+        ),
+        QueryResult::Tables(tables) => (
+            vec!["table"],
+            tables.iter().map(|table| vec![Cell::text(table)]).collect(),
+        ),
+        QueryResult::Table(info) => (vec!["field", "value"], fields(&table_fields(info))),
+        QueryResult::Stats(stats) => (vec!["field", "value"], fields(&stats_fields(stats))),
+    };
+    Grid { headers, rows }
+}
+
+///A DESCRIBE or INFO field, typed so JSON can tell numbers from text
+enum Field {
+    Number(u64),
+    Text(String),
+    ///not set, null in JSON and blank elsewhere
+    Missing,
+}
+
+impl Field {
+    fn number(n: Option<u64>) -> Self {
+        n.map_or(Field::Missing, Field::Number)
+    }
+
+    fn text(text: Option<&String>) -> Self {
+        text.map_or(Field::Missing, |text| Field::Text(text.to_string()))
+    }
+
+    fn json(&self) -> String {
+        match self {
+            Field::Number(n) => n.to_string(),
+            Field::Text(text) => json_string(text),
+            Field::Missing => "null".to_string(),
+        }
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Field::Number(n) => write!(f, "{}", n),
+            Field::Text(text) => write!(f, "{}", text),
+            Field::Missing => Ok(()),
+        }
+    }
+}
+
+fn fields(fields: &[(String, Field)]) -> Vec<Vec<Cell>> {
+    fields
+        .iter()
+        .map(|(name, val)| vec![Cell::text(name), Cell::text(val)])
+        .collect()
+}
+
+fn table_fields(info: &TableInfo) -> Vec<(String, Field)> {
+    vec![
+        ("table".to_string(), Field::Text(info.name.to_string())),
+        (
+            "description".to_string(),
+            Field::text(info.options.description.as_ref()),
+        ),
+        ("created_at".to_string(), Field::number(info.created_at)),
+        ("modified_at".to_string(), Field::number(info.modified_at)),
+        ("keys".to_string(), Field::Number(info.keys as u64)),
+        (
+            "approx_bytes".to_string(),
+            Field::Number(info.approx_bytes as u64),
+        ),
+        (
+            "default_ttl_secs".to_string(),
+            Field::number(info.options.default_ttl.map(|ttl| ttl.as_secs())),
+        ),
+    ]
+}
+
+fn stats_fields(stats: &Stats) -> Vec<(String, Field)> {
+    let mut fields = vec![
+        (
+            "uptime_secs".to_string(),
+            Field::Number(stats.uptime.as_secs()),
+        ),
+        ("tables".to_string(), Field::Number(stats.tables as u64)),
+        ("keys".to_string(), Field::Number(stats.keys as u64)),
+        (
+            "expiring_keys".to_string(),
+            Field::Number(stats.expiring_keys as u64),
+        ),
+        (
+            "snapshot_bytes".to_string(),
+            Field::Number(stats.snapshot_bytes),
+        ),
+        ("wal_bytes".to_string(), Field::Number(stats.wal_bytes)),
+        (
+            "operations_since_checkpoint".to_string(),
+            Field::Number(stats.operations_since_checkpoint as u64),
+        ),
+        (
+            "unsynced_wal_entries".to_string(),
+            Field::Number(stats.unsynced_wal_entries as u64),
+        ),
+        (
+            "total_checkpoints".to_string(),
+            Field::Number(stats.total_checkpoints as u64),
+        ),
+        (
+            "last_checkpoint_error".to_string(),
+            Field::text(stats.last_checkpoint_error.as_ref()),
+        ),
+    ];
+    fields.extend(
+        stats
+            .commands
+            .iter()
+            .map(|(command, count)| (format!("commands.{}", command), Field::Number(*count))),
+    );
+    fields
+}
+
+fn table(grid: &Grid) -> String {
+    let rows: Vec<Vec<String>> = grid
+        .rows
+        .iter()
+        .map(|row| row.iter().map(Cell::display).collect())
+        .collect();
+    let mut widths: Vec<usize> = grid.headers.iter().map(|header| header.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        let cells: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        format!("| {} |", cells.join(" | "))
+    };
+    let rule = format!(
+        "+{}+",
+        widths
+            .iter()
+            .map(|width| "-".repeat(width + 2))
+            .collect::<Vec<_>>()
+            .join("+")
+    );
+    let mut lines = vec![rule.clone(), line(grid.headers.clone()), rule.clone()];
+    lines.extend(
+        rows.iter()
+            .map(|row| line(row.iter().map(String::as_str).collect())),
+    );
+    lines.push(rule);
+    lines.push(match rows.len() {
+        1 => "1 row".to_string(),
+        count => format!("{} rows", count),
+    });
+    lines.join("\n")
+}
+
+fn csv(grid: &Grid) -> String {
+    let line = |cells: Vec<String>| {
+        cells
+            .iter()
+            .map(|cell| csv_field(cell))
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let mut lines = vec![line(grid.headers.iter().map(|h| h.to_string()).collect())];
+    lines.extend(
+        grid.rows
+            .iter()
+            .map(|row| line(row.iter().map(Cell::raw).collect())),
+    );
+    lines.join("\n")
+}
+
+///Quote a field if it holds a comma, quote or line break, doubling any quotes
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn json(result: &QueryResult) -> String {
+    let entry = |key: &str, val: Option<&Value>| {
+        let found = match val {
+            Some(_) => String::new(),
+            None => ",\"found\":false".to_string(),
+        };
+        format!(
+            "{{\"key\":{},\"value\":{}{}}}",
+            json_string(key),
+            val.map_or("null".to_string(), json_value),
+            found
+        )
+    };
+    match result {
+        QueryResult::Value(val) => json_value(val),
+        QueryResult::Values(vals) => {
+            json_array(vals.iter().map(|(key, val)| entry(key, val.as_ref())))
+        }
+        QueryResult::Rows { rows, cursor } => {
+            let rows = json_array(rows.iter().map(|(key, val)| entry(key, Some(val))));
+            match cursor {
+                Some(cursor) => format!(
+                    "{{\"cursor\":{},\"rows\":{}}}",
+                    json_string(&cursor.to_string()),
+                    rows
+                ),
+                None => rows,
+            }
+        }
+        QueryResult::Keys { keys, more } => format!(
+            "{{\"keys\":{},\"more\":{}}}",
+            json_array(keys.iter().map(|key| json_string(key))),
+            more
+        ),
+        QueryResult::Column(vals) => json_array(vals.iter().map(json_value)),
+        QueryResult::Count(count) => count.to_string(),
+        QueryResult::Ack(message) | QueryResult::NotApplied(message) => format!(
+            "{{\"ok\":{},\"message\":{}}}",
+            matches!(result, QueryResult::Ack(_)),
+            json_string(message)
+        ),
+        QueryResult::Tables(tables) => json_array(tables.iter().map(|table| json_string(table))),
+        QueryResult::Table(info) => json_object(&table_fields(info)),
+        QueryResult::Stats(stats) => json_object(&stats_fields(stats)),
+    }
+}
+
+fn json_object(fields: &[(String, Field)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(name, val)| format!("{}:{}", json_string(name), val.json()))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn json_array(items: impl Iterator<Item = String>) -> String {
+    format!("[{}]", items.collect::<Vec<_>>().join(","))
+}
+
+///Bytes have no JSON type, they are written as the same `x'..'` literal the REPL takes
+fn json_value(val: &Value) -> String {
+    match val {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Int(i) => i.to_string(),
+        Value::Float(x) if x.is_finite() => format!("{:?}", x),
+        Value::Float(_) => "null".to_string(),
+        Value::Str(s) => json_string(s),
+        Value::Bytes(_) => json_string(&val.to_string()),
+        Value::List(items) => json_array(items.iter().map(json_value)),
+        Value::Map(entries) => format!(
+            "{{{}}}",
+            entries
+                .iter()
+                .map(|(key, val)| format!("{}:{}", json_string(key), json_value(val)))
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> QueryResult {
+        QueryResult::Rows {
+            rows: vec![
+                ("a".to_string(), Value::from("x, \"y\"")),
+                ("bb".to_string(), Value::Int(42)),
+            ],
+            cursor: None,
+        }
+    }
+
+    #[test]
+    fn test_render_table() {
+        assert_eq!(
+            render(&rows(), OutputFormat::Table),
+            "+-----+------------+\n\
+             | key | value      |\n\
+             +-----+------------+\n\
+             | a   | \"x, \\\"y\\\"\" |\n\
+             | bb  | 42         |\n\
+             +-----+------------+\n\
+             2 rows"
+        );
+    }
+
+    #[test]
+    fn test_render_csv() {
+        assert_eq!(
+            render(&rows(), OutputFormat::Csv),
+            "key,value\na,\"x, \"\"y\"\"\"\nbb,42"
+        );
+        let missing = QueryResult::Values(vec![("k".to_string(), None)]);
+        assert_eq!(
+            render(&missing, OutputFormat::Csv),
+            "key,value,found\nk,null,false"
+        );
+    }
+
+    #[test]
+    fn test_render_json() {
+        assert_eq!(
+            render(&rows(), OutputFormat::Json),
+            r#"[{"key":"a","value":"x, \"y\""},{"key":"bb","value":42}]"#
+        );
+        let nested = Value::List(vec![
+            Value::Float(1.0),
+            Value::Bytes(vec![0xff]),
+            Value::Map([("k\n".to_string(), Value::Null)].into()),
+        ]);
+        assert_eq!(
+            render(&QueryResult::Value(nested), OutputFormat::Json),
+            r#"[1.0,"x'ff'",{"k\n":null}]"#
+        );
+        assert_eq!(
+            render(
+                &QueryResult::Column(vec![Value::Int(1), Value::from("a")]),
+                OutputFormat::Json
+            ),
+            r#"[1,"a"]"#
+        );
+        assert_eq!(
+            render(
+                &QueryResult::Values(vec![("k".to_string(), None)]),
+                OutputFormat::Json
+            ),
+            r#"[{"key":"k","value":null,"found":false}]"#
+        );
+        assert_eq!(
+            render(
+                &QueryResult::NotApplied("Not written".to_string()),
+                OutputFormat::Json
+            ),
+            r#"{"ok":false,"message":"Not written"}"#
+        );
+        //a table named like a number is still a string
+        let info = TableInfo {
+            name: "007".to_string(),
+            created_at: Some(1),
+            modified_at: None,
+            keys: 2,
+            approx_bytes: 10,
+            options: Default::default(),
+        };
+        assert_eq!(
+            render(&QueryResult::Table(info), OutputFormat::Json),
+            r#"{"table":"007","description":null,"created_at":1,"modified_at":null,"keys":2,"approx_bytes":10,"default_ttl_secs":null}"#
+        );
+    }
+
+    #[test]
+    fn test_parse_format() {
+        for format in OutputFormat::ALL {
+            assert_eq!(format.to_string().parse(), Ok(format));
+        }
+        assert_eq!("JSON".parse(), Ok(OutputFormat::Json));
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+}
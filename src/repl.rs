@@ -0,0 +1,215 @@
+use rusty_db::OutputFormat;
+use rustyline::{
+    Context, Helper, completion::Completer, highlight::Highlighter, hint::Hinter,
+    validate::Validator,
+};
+
+///Everything the REPL accepts as a first word
+const COMMANDS: &[&str] = &[
+    "ALTER",
+    "BEGIN",
+    "CAS",
+    "CHECKPOINT",
+    "COMMIT",
+    "COMPACT",
+    "COPY",
+    "COUNT",
+    "CREATE",
+    "DECR",
+    "DECRBY",
+    "DEL",
+    "DELETE",
+    "DESCRIBE",
+    "DROP",
+    "EXIT",
+    "EXPIRE",
+    "GET",
+    "HELP",
+    "INCR",
+    "INCRBY",
+    "INCRBYFLOAT",
+    "INFO",
+    "INSERT",
+    "KEYS",
+    "LIST",
+    "MDEL",
+    "MGET",
+    "MSET",
+    "PERSIST",
+    "PREFIX",
+    "RENAME",
+    "ROLLBACK",
+    "SCAN",
+    "SELECT",
+    "SET",
+    "SETNX",
+    "TRUNCATE",
+    "TTL",
+    "UPDATE",
+];
+
+///Commands whose first argument is an existing table, CREATE's is a new one
+const TABLE_COMMANDS: &[&str] = &[
+    "ALTER",
+    "CAS",
+    "COPY",
+    "COUNT",
+    "DECR",
+    "DECRBY",
+    "DEL",
+    "DESCRIBE",
+    "DROP",
+    "EXPIRE",
+    "GET",
+    "INCR",
+    "INCRBY",
+    "INCRBYFLOAT",
+    "KEYS",
+    "MDEL",
+    "MGET",
+    "MSET",
+    "PERSIST",
+    "PREFIX",
+    "RENAME",
+    "SCAN",
+    "SET",
+    "SETNX",
+    "TRUNCATE",
+    "TTL",
+    "UPDATE",
+];
+
+///Per command, the word from which its options may start and the options it takes
+const OPTIONS: &[(&str, usize, &[&str])] = &[
+    ("SET", 4, &["EX", "PX", "NX", "XX"]),
+    ("SCAN", 2, &["FROM", "TO", "LIMIT", "MATCH", "COUNT"]),
+    ("PREFIX", 3, &["LIMIT"]),
+    ("KEYS", 2, &["LIMIT", "AFTER"]),
+    ("ALTER", 2, &["DESCRIPTION", "TTL", "NONE"]),
+];
+
+///Tab completion for the REPL. Table names are whatever the database had when the
+///prompt was shown, the REPL refreshes them before every line.
+#[derive(Debug, Default)]
+pub struct ReplHelper {
+    pub tables: Vec<String>,
+}
+
+impl ReplHelper {
+    ///Where the word under the cursor starts and what it could be completed to
+    fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let before = &line[..pos];
+        let start = before.rfind(char::is_whitespace).map_or(0, |at| at + 1);
+        let word = &before[start..];
+        let words: Vec<&str> = before[..start].split_whitespace().collect();
+        let Some(command) = words.first().map(|command| command.to_uppercase()) else {
+            if word.starts_with('\\') {
+                let meta = Some("\\format".to_string()).filter(|meta| meta.starts_with(word));
+                return (start, meta.into_iter().collect());
+            }
+            return (start, keywords(COMMANDS, word));
+        };
+        if command == "\\FORMAT" {
+            if words.len() > 1 {
+                return (start, Vec::new());
+            }
+            let formats = OutputFormat::ALL
+                .iter()
+                .map(OutputFormat::to_string)
+                .filter(|format| format.starts_with(word))
+                .collect();
+            return (start, formats);
+        }
+
+        if words.len() == 1 && TABLE_COMMANDS.contains(&command.as_str()) {
+            let tables = self
+                .tables
+                .iter()
+                .filter(|table| table.starts_with(word))
+                .cloned()
+                .collect();
+            return (start, tables);
+        }
+        let options = OPTIONS
+            .iter()
+            .find(|(name, from, _)| *name == command && words.len() >= *from)
+            .map_or_else(Vec::new, |(_, _, options)| keywords(options, word));
+        (start, options)
+    }
+}
+
+///The keywords starting with `word`, lower case if that's how it was typed
+fn keywords(keywords: &[&str], word: &str) -> Vec<String> {
+    let upper = word.to_uppercase();
+    let lower = word.chars().any(char::is_lowercase) && !word.chars().any(char::is_uppercase);
+    keywords
+        .iter()
+        .filter(|keyword| keyword.starts_with(&upper))
+        .map(|keyword| {
+            if lower {
+                keyword.to_lowercase()
+            } else {
+                keyword.to_string()
+            }
+        })
+        .collect()
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(line, pos))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complete(line: &str) -> (usize, Vec<String>) {
+        let helper = ReplHelper {
+            tables: vec![
+                "users".to_string(),
+                "uploads".to_string(),
+                "orders".to_string(),
+            ],
+        };
+        helper.candidates(line, line.len())
+    }
+
+    #[test]
+    fn test_complete() {
+        assert_eq!(complete("DES"), (0, vec!["DESCRIBE".to_string()]));
+        assert_eq!(complete("inc").1, ["incr", "incrby", "incrbyfloat"]);
+        assert_eq!(
+            complete("GET u"),
+            (4, vec!["users".to_string(), "uploads".to_string()])
+        );
+        assert_eq!(complete("get  ").1, ["users", "uploads", "orders"]);
+        //new tables and keys aren't completed
+        assert!(complete("CREATE u").1.is_empty());
+        assert!(complete("GET users u").1.is_empty());
+        assert!(complete("LIST ").1.is_empty());
+        assert_eq!(complete("SET users k v E").1, ["EX"]);
+        assert!(complete("SET users k E").1.is_empty());
+        assert_eq!(complete("scan users m").1, ["match"]);
+        assert_eq!(complete("\\f").1, ["\\format"]);
+        assert_eq!(complete("\\format t").1, ["text", "table"]);
+    }
+}
@@ -0,0 +1,382 @@
+use std::io::{BufRead, Read, Write};
+
+use crate::{
+    err_types::RustyDbErr,
+    query_result::QueryResult,
+    tokenizer::{Token, tokenize},
+    value::Value,
+};
+
+///Protocol version negotiated with HELLO, connections start on RESP2
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Resp2,
+    Resp3,
+}
+
+///A reply in the Redis serialization protocol
+#[derive(Debug, Clone, PartialEq)]
+pub enum RespValue {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Null,
+    Array(Vec<RespValue>),
+    ///RESP3 map, sent as a flat array of key/value pairs to RESP2 clients
+    Map(Vec<(RespValue, RespValue)>),
+}
+
+impl RespValue {
+    pub fn bulk(s: &str) -> Self {
+        RespValue::Bulk(s.as_bytes().to_vec())
+    }
+
+    ///Redis clients expect plain strings back, so only values without a natural
+    ///string form are sent as their literal text
+    pub fn from_value(value: &Value) -> Self {
+        match value {
+            Value::Null => RespValue::Null,
+            Value::Str(s) => RespValue::bulk(s),
+            Value::Bytes(bytes) => RespValue::Bulk(bytes.clone()),
+            other => RespValue::bulk(&other.to_string()),
+        }
+    }
+
+    ///The natural reply for a command's result: counts are integers, lists arrays, rows
+    ///maps, a write that did nothing nil, and DESCRIBE and INFO text like redis' INFO
+    pub fn from_result(result: &QueryResult) -> Self {
+        let bulks =
+            |items: &[String]| RespValue::Array(items.iter().map(|s| RespValue::bulk(s)).collect());
+        match result {
+            QueryResult::Value(val) => RespValue::from_value(val),
+            QueryResult::Values(vals) => RespValue::Array(
+                vals.iter()
+                    .map(|(_, val)| val.as_ref().map_or(RespValue::Null, RespValue::from_value))
+                    .collect(),
+            ),
+            QueryResult::Rows { rows, cursor } => {
+                let rows = RespValue::Map(
+                    rows.iter()
+                        .map(|(key, val)| (RespValue::bulk(key), RespValue::from_value(val)))
+                        .collect(),
+                );
+                match cursor {
+                    Some(cursor) => {
+                        RespValue::Array(vec![RespValue::bulk(&cursor.to_string()), rows])
+                    }
+                    None => rows,
+                }
+            }
+            QueryResult::Keys { keys, .. } => bulks(keys),
+            QueryResult::Column(vals) => {
+                RespValue::Array(vals.iter().map(RespValue::from_value).collect())
+            }
+            QueryResult::Count(count) => RespValue::Integer(*count as i64),
+            QueryResult::Ack(message) => RespValue::Simple(message.to_string()),
+            QueryResult::NotApplied(_) => RespValue::Null,
+            QueryResult::Tables(tables) => bulks(tables),
+            QueryResult::Table(_) | QueryResult::Stats(_) => RespValue::bulk(&result.to_string()),
+        }
+    }
+
+    ///Serialize the value for a client speaking `protocol`
+    pub fn encode(&self, protocol: Protocol) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(protocol, &mut out);
+        out
+    }
+
+    fn encode_into(&self, protocol: Protocol, out: &mut Vec<u8>) {
+        match self {
+            RespValue::Simple(s) => {
+                //acks echo names the client chose, a newline would start a reply of its own
+                let s = s.replace(['\r', '\n'], " ");
+                out.extend_from_slice(format!("+{}\r\n", s).as_bytes())
+            }
+            RespValue::Error(s) => {
+                //newlines would end the error early
+                let s = s.replace(['\r', '\n'], " ");
+                out.extend_from_slice(format!("-{}\r\n", s).as_bytes())
+            }
+            RespValue::Integer(i) => out.extend_from_slice(format!(":{}\r\n", i).as_bytes()),
+            RespValue::Bulk(bytes) => {
+                out.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+                out.extend_from_slice(bytes);
+                out.extend_from_slice(b"\r\n");
+            }
+            RespValue::Null => match protocol {
+                Protocol::Resp2 => out.extend_from_slice(b"$-1\r\n"),
+                Protocol::Resp3 => out.extend_from_slice(b"_\r\n"),
+            },
+            RespValue::Array(items) => {
+                out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.encode_into(protocol, out);
+                }
+            }
+            RespValue::Map(pairs) => {
+                match protocol {
+                    Protocol::Resp2 => {
+                        out.extend_from_slice(format!("*{}\r\n", pairs.len() * 2).as_bytes())
+                    }
+                    Protocol::Resp3 => {
+                        out.extend_from_slice(format!("%{}\r\n", pairs.len()).as_bytes())
+                    }
+                }
+                for (key, val) in pairs {
+                    key.encode_into(protocol, out);
+                    val.encode_into(protocol, out);
+                }
+            }
+        }
+    }
+}
+
+///Most arguments a request may have, same as redis
+pub const MAX_ARGS: usize = 1024 * 1024;
+
+///Longest bulk string a request may contain, same as redis' 512MB
+pub const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+///Longest inline command or header line, same as redis' 64KB
+pub const MAX_INLINE_LEN: usize = 64 * 1024;
+
+///Arguments we make room for before they have arrived
+const PREALLOCATE_ARGS: usize = 64;
+
+///Read one request, either a RESP array of bulk strings or an inline command.
+///Returns `None` when the client has closed the connection.
+pub fn read_request(reader: &mut impl BufRead) -> Result<Option<Vec<String>>, RustyDbErr> {
+    let line = match read_line(reader)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let Some(count) = line.strip_prefix('*') else {
+        //inline command, as typed into telnet
+        return tokenize(&line)
+            .map(|tokens| Some(tokens.iter().map(Token::text).collect()))
+            .map_err(|e| RustyDbErr::InvalidQuery(e.to_string()));
+    };
+    let count = parse_len(count)?;
+    if count > MAX_ARGS {
+        return Err(protocol_error(format!(
+            "too many arguments, at most {} allowed",
+            MAX_ARGS
+        )));
+    }
+    //the count is only a claim, let the vec grow as arguments actually arrive
+    let mut args = Vec::with_capacity(count.min(PREALLOCATE_ARGS));
+    for _ in 0..count {
+        let header = read_line(reader)?.ok_or_else(unexpected_eof)?;
+        let len = header
+            .strip_prefix('$')
+            .ok_or_else(|| protocol_error(format!("expected '$', got '{}'", header)))?;
+        let len = parse_len(len)?;
+        if len > MAX_BULK_LEN {
+            return Err(protocol_error(format!(
+                "bulk string too long, at most {} bytes allowed",
+                MAX_BULK_LEN
+            )));
+        }
+        //payload plus trailing \r\n
+        let expected = len.checked_add(2).ok_or_else(unexpected_eof)?;
+        let mut data = Vec::new();
+        reader
+            .take(expected as u64)
+            .read_to_end(&mut data)
+            .map_err(|e| RustyDbErr::io("reading request", e))?;
+        if data.len() < expected {
+            return Err(unexpected_eof());
+        }
+        if !data.ends_with(b"\r\n") {
+            return Err(protocol_error(
+                "bulk string not terminated by CRLF".to_string(),
+            ));
+        }
+        data.truncate(len);
+        let arg = String::from_utf8(data)
+            .map_err(|_| protocol_error("arguments must be valid utf8".to_string()))?;
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+///Write a reply and flush it to the client
+pub fn write_reply(
+    writer: &mut impl Write,
+    reply: &RespValue,
+    protocol: Protocol,
+) -> Result<(), RustyDbErr> {
+    writer
+        .write_all(&reply.encode(protocol))
+        .and_then(|_| writer.flush())
+        .map_err(|e| RustyDbErr::io("writing reply", e))
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<Option<String>, RustyDbErr> {
+    //a client that never sends a newline mustn't have us buffer forever
+    let limit = MAX_INLINE_LEN + 2;
+    let mut line = Vec::new();
+    let read = reader
+        .take(limit as u64)
+        .read_until(b'\n', &mut line)
+        .map_err(|e| RustyDbErr::io("reading request", e))?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if read == limit && !line.ends_with(b"\n") {
+        return Err(protocol_error(format!(
+            "line too long, at most {} bytes allowed",
+            MAX_INLINE_LEN
+        )));
+    }
+    let line = String::from_utf8(line)
+        .map_err(|_| protocol_error("requests must be valid utf8".to_string()))?;
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+fn parse_len(len: &str) -> Result<usize, RustyDbErr> {
+    len.parse::<usize>()
+        .map_err(|_| protocol_error(format!("invalid length '{}'", len)))
+}
+
+fn protocol_error(msg: String) -> RustyDbErr {
+    RustyDbErr::InvalidQuery(format!("Protocol error: {}", msg))
+}
+
+fn unexpected_eof() -> RustyDbErr {
+    protocol_error("unexpected end of request".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_read_array_request() {
+        let mut input =
+            Cursor::new(b"*3\r\n$3\r\nSET\r\n$2\r\nk1\r\n$12\r\nhello\r\nworld\r\n".to_vec());
+        assert_eq!(
+            read_request(&mut input).unwrap(),
+            Some(vec![
+                "SET".to_string(),
+                "k1".to_string(),
+                "hello\r\nworld".to_string()
+            ])
+        );
+        assert_eq!(read_request(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_inline_request() {
+        let mut input = Cursor::new(b"SET k1 \"a b\"\r\n".to_vec());
+        assert_eq!(
+            read_request(&mut input).unwrap(),
+            Some(vec!["SET".to_string(), "k1".to_string(), "a b".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_read_bad_request() {
+        let mut input = Cursor::new(b"*1\r\n:3\r\n".to_vec());
+        assert!(read_request(&mut input).is_err());
+        let mut input = Cursor::new(b"*2\r\n$3\r\nGET\r\n".to_vec());
+        assert!(read_request(&mut input).is_err());
+        //huge claims are refused before anything is allocated for them
+        let mut input = Cursor::new(b"*4000000000000\r\n".to_vec());
+        assert!(matches!(
+            read_request(&mut input),
+            Err(RustyDbErr::InvalidQuery(_))
+        ));
+        let mut input = Cursor::new(b"*1\r\n$18446744073709551615\r\n".to_vec());
+        assert!(matches!(
+            read_request(&mut input),
+            Err(RustyDbErr::InvalidQuery(_))
+        ));
+        let mut input = Cursor::new(b"*1\r\n$100\r\nshort\r\n".to_vec());
+        assert!(read_request(&mut input).is_err());
+    }
+
+    #[test]
+    fn test_read_long_line() {
+        //an inline command or header that never ends is cut off at the limit
+        let mut input = Cursor::new(vec![b'a'; MAX_INLINE_LEN * 2]);
+        assert!(matches!(
+            read_request(&mut input),
+            Err(RustyDbErr::InvalidQuery(why)) if why.contains("line too long")
+        ));
+        let mut input = Cursor::new(format!("*1\r\n${}", "9".repeat(MAX_INLINE_LEN)).into_bytes());
+        assert!(read_request(&mut input).is_err());
+        //right at the limit is fine
+        let mut line = "a".repeat(MAX_INLINE_LEN);
+        line.push_str("\r\n");
+        let mut input = Cursor::new(line.into_bytes());
+        assert_eq!(
+            read_request(&mut input).unwrap(),
+            Some(vec!["a".repeat(MAX_INLINE_LEN)])
+        );
+    }
+
+    #[test]
+    fn test_encode() {
+        let reply = RespValue::Array(vec![
+            RespValue::bulk("v1"),
+            RespValue::Null,
+            RespValue::Integer(3),
+        ]);
+        assert_eq!(
+            reply.encode(Protocol::Resp2),
+            b"*3\r\n$2\r\nv1\r\n$-1\r\n:3\r\n"
+        );
+        assert_eq!(
+            reply.encode(Protocol::Resp3),
+            b"*3\r\n$2\r\nv1\r\n_\r\n:3\r\n"
+        );
+        let map = RespValue::Map(vec![(RespValue::bulk("proto"), RespValue::Integer(3))]);
+        assert_eq!(map.encode(Protocol::Resp3), b"%1\r\n$5\r\nproto\r\n:3\r\n");
+        assert_eq!(map.encode(Protocol::Resp2), b"*2\r\n$5\r\nproto\r\n:3\r\n");
+        assert_eq!(
+            RespValue::Error("ERR bad\nthing".to_string()).encode(Protocol::Resp2),
+            b"-ERR bad thing\r\n"
+        );
+    }
+
+    #[test]
+    fn test_from_result() {
+        let rows = QueryResult::Rows {
+            rows: vec![("k".to_string(), Value::Int(1))],
+            cursor: None,
+        };
+        assert_eq!(
+            RespValue::from_result(&rows),
+            RespValue::Map(vec![(RespValue::bulk("k"), RespValue::bulk("1"))])
+        );
+        assert_eq!(
+            RespValue::from_result(&QueryResult::Count(2)),
+            RespValue::Integer(2)
+        );
+        assert_eq!(
+            RespValue::from_result(&QueryResult::Ack("Created table t".to_string())),
+            RespValue::Simple("Created table t".to_string())
+        );
+        let injected = QueryResult::Ack("Created table x\r\n+OK".to_string());
+        assert_eq!(
+            RespValue::from_result(&injected).encode(Protocol::Resp2),
+            b"+Created table x  +OK\r\n"
+        );
+        assert_eq!(
+            RespValue::from_result(&QueryResult::NotApplied("Not written".to_string())),
+            RespValue::Null
+        );
+        assert_eq!(
+            RespValue::from_result(&QueryResult::Values(vec![
+                ("a".to_string(), Some(Value::from("x"))),
+                ("b".to_string(), None),
+            ])),
+            RespValue::Array(vec![RespValue::bulk("x"), RespValue::Null])
+        );
+    }
+}
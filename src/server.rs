@@ -0,0 +1,509 @@
+use std::{
+    io::{BufReader, BufWriter},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex, PoisonError},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    Result,
+    command::{Command, PutCondition, parse_scan_options, parse_set_options, parse_tokens},
+    cursor::Cursor,
+    db::{DEFAULT_SCAN_COUNT, RustyDb},
+    err_types::{ParseError, RustyDbErr},
+    expiry::{DEFAULT_SWEEP_INTERVAL, spawn_sweeper},
+    pattern::KeyPattern,
+    resp::{Protocol, RespValue, read_request, write_reply},
+    tokenizer::Token,
+    value::Value,
+};
+
+///Table that connections use until they SELECT another one
+pub const DEFAULT_TABLE: &str = "default";
+
+///TCP server speaking RESP2/RESP3, so redis clients can talk to a [`RustyDb`]
+pub struct Server {
+    listener: TcpListener,
+    db: Arc<Mutex<RustyDb>>,
+}
+
+///Per connection state
+struct Session {
+    protocol: Protocol,
+    ///table GET/SET/DEL work on, changed with SELECT
+    table: String,
+}
+
+impl Server {
+    ///Listen on `addr`, creating the default table if the database lacks one
+    pub fn bind(mut db: RustyDb, addr: &str) -> Result<Self> {
+        if !db.list_tables().iter().any(|t| t == DEFAULT_TABLE) {
+            db.create_table(DEFAULT_TABLE)?;
+        }
+        let listener =
+            TcpListener::bind(addr).map_err(|e| RustyDbErr::io(format!("binding {}", addr), e))?;
+        Ok(Self {
+            listener,
+            db: Arc::new(Mutex::new(db)),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener
+            .local_addr()
+            .map_err(|e| RustyDbErr::io("reading listener address", e))
+    }
+
+    ///Accept connections forever, one thread per client
+    pub fn run(self) -> Result<()> {
+        spawn_sweeper(Arc::downgrade(&self.db), DEFAULT_SWEEP_INTERVAL);
+        for stream in self.listener.incoming() {
+            let stream = stream.map_err(|e| RustyDbErr::io("accepting connection", e))?;
+            let db = Arc::clone(&self.db);
+            thread::spawn(move || {
+                if let Err(why) = handle_connection(stream, &db) {
+                    eprintln!("Connection error: {}", why);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+fn handle_connection(stream: TcpStream, db: &Mutex<RustyDb>) -> Result<()> {
+    let mut reader = BufReader::new(
+        stream
+            .try_clone()
+            .map_err(|e| RustyDbErr::io("cloning connection", e))?,
+    );
+    let mut writer = BufWriter::new(stream);
+    let mut session = Session {
+        protocol: Protocol::Resp2,
+        table: DEFAULT_TABLE.to_string(),
+    };
+
+    loop {
+        let args = match read_request(&mut reader) {
+            Ok(Some(args)) => args,
+            Ok(None) => return Ok(()),
+            Err(why) => {
+                //we can't tell where the next request starts, so give up on the client
+                let reply = error(why);
+                return write_reply(&mut writer, &reply, session.protocol);
+            }
+        };
+        if args.is_empty() {
+            continue;
+        }
+        let quit = args[0].eq_ignore_ascii_case("QUIT");
+        let reply = dispatch(&args, &mut session, db);
+        write_reply(&mut writer, &reply, session.protocol)?;
+        if quit {
+            return Ok(());
+        }
+    }
+}
+
+///Commands `dispatch` answers without going through `RustyDb::execute`, bar the
+///cursor form of SCAN. Any other arguments to them get redis' arity error.
+const ANSWERED_HERE: &[&str] = &[
+    "PING",
+    "ECHO",
+    "QUIT",
+    "COMMAND",
+    "HELLO",
+    "SELECT",
+    "GET",
+    "SET",
+    "SETNX",
+    "CAS",
+    "INCR",
+    "DECR",
+    "INCRBY",
+    "DECRBY",
+    "INCRBYFLOAT",
+    "EXPIRE",
+    "TTL",
+    "PERSIST",
+    "DEL",
+    "MGET",
+    "MSET",
+    "KEYS",
+    "EXISTS",
+];
+
+///Run one request, redis commands first, then anything `parse_tokens` understands
+fn dispatch(args: &[String], session: &mut Session, db: &Mutex<RustyDb>) -> RespValue {
+    let name = args[0].to_uppercase();
+    let mut db = db.lock().unwrap_or_else(PoisonError::into_inner);
+    //anything that falls through to `execute` is counted there
+    if ANSWERED_HERE.contains(&name.as_str()) {
+        db.record_command(&name);
+    }
+    match (name.as_str(), &args[1..]) {
+        ("PING", []) => RespValue::Simple("PONG".to_string()),
+        ("PING", [msg]) | ("ECHO", [msg]) => RespValue::bulk(msg),
+        ("QUIT", _) => RespValue::Simple("OK".to_string()),
+        //redis-cli asks for command docs on startup, we have none to give
+        ("COMMAND", _) => RespValue::Array(Vec::new()),
+        ("HELLO", rest) => hello(rest, session),
+        ("SELECT", [table]) => {
+            if db.list_tables().contains(table) {
+                session.table = table.to_string();
+                RespValue::Simple("OK".to_string())
+            } else {
+                error(RustyDbErr::TableNotFound(table.to_string()))
+            }
+        }
+        ("GET", [key]) => match db.get(&session.table, key) {
+            Ok(val) => RespValue::from_value(val),
+            Err(RustyDbErr::KeyNotFound(_)) => RespValue::Null,
+            Err(why) => error(why),
+        },
+        ("SET", [key, val, options @ ..]) => {
+            let (ttl, condition) = match parse_set_options(options) {
+                Ok(options) => options,
+                Err(why) => return parse_error(why),
+            };
+            let (table, key, val) = (session.table.clone(), key.to_string(), val.to_string());
+            match db.put_if(table, key, val, &condition, ttl) {
+                Ok(true) => RespValue::Simple("OK".to_string()),
+                //redis answers a SET NX/XX that didn't happen with nil
+                Ok(false) => RespValue::Null,
+                Err(why) => error(why),
+            }
+        }
+        ("SETNX", [key, val]) => {
+            let (table, key, val) = (session.table.clone(), key.to_string(), val.to_string());
+            match db.put_if(table, key, val, &PutCondition::IfAbsent, None) {
+                Ok(written) => RespValue::Integer(written as i64),
+                Err(why) => error(why),
+            }
+        }
+        //like SET, in the session's table with both values taken as the strings sent
+        ("CAS", [key, expected, val]) => {
+            let condition = PutCondition::IfEquals(Value::from(expected.as_str()));
+            let (table, key, val) = (
+                session.table.clone(),
+                key.to_string(),
+                Value::from(val.as_str()),
+            );
+            match db.put_if(table, key, val, &condition, None) {
+                Ok(true) => RespValue::Simple("OK".to_string()),
+                Ok(false) => RespValue::Null,
+                Err(why) => error(why),
+            }
+        }
+        ("INCR", [key]) => counter_reply(db.incr_by(&session.table, key, 1)),
+        ("DECR", [key]) => counter_reply(db.incr_by(&session.table, key, -1)),
+        ("INCRBY", [key, by]) | ("DECRBY", [key, by]) => {
+            let by = match by.parse::<i64>() {
+                Ok(by) if name == "INCRBY" => Some(by),
+                Ok(by) => by.checked_neg(),
+                Err(_) => None,
+            };
+            match by {
+                Some(by) => counter_reply(db.incr_by(&session.table, key, by)),
+                None => RespValue::Error("ERR value is not an integer or out of range".to_string()),
+            }
+        }
+        ("INCRBYFLOAT", [key, by]) => {
+            let Some(by) = by.parse::<f64>().ok().filter(|by| by.is_finite()) else {
+                return RespValue::Error("ERR value is not a valid float".to_string());
+            };
+            match db.incr_by_float(&session.table, key, by) {
+                Ok(val) => RespValue::bulk(&val.to_string()),
+                Err(why) => error(why),
+            }
+        }
+        ("EXPIRE", [key, secs]) => {
+            let Ok(secs) = secs.parse::<u64>() else {
+                return RespValue::Error("ERR value is not an integer or out of range".to_string());
+            };
+            match db.expire(&session.table, key, Duration::from_secs(secs)) {
+                Ok(()) => RespValue::Integer(1),
+                Err(RustyDbErr::KeyNotFound(_)) => RespValue::Integer(0),
+                Err(why) => error(why),
+            }
+        }
+        //redis uses -1 for no expiry and -2 for no key
+        ("TTL", [key]) => match db.ttl(&session.table, key) {
+            Ok(Some(left)) => RespValue::Integer(((left.as_millis() + 500) / 1000) as i64),
+            Ok(None) => RespValue::Integer(-1),
+            Err(RustyDbErr::KeyNotFound(_)) => RespValue::Integer(-2),
+            Err(why) => error(why),
+        },
+        ("PERSIST", [key]) => match db.persist(&session.table, key) {
+            Ok(persisted) => RespValue::Integer(persisted as i64),
+            Err(RustyDbErr::KeyNotFound(_)) => RespValue::Integer(0),
+            Err(why) => error(why),
+        },
+        ("DEL", keys) if !keys.is_empty() => match db.mdel(&session.table, keys) {
+            Ok(deleted) => RespValue::Integer(deleted as i64),
+            Err(why) => error(why),
+        },
+        ("MGET", keys) if !keys.is_empty() => match db.mget(&session.table, keys) {
+            Ok(vals) => RespValue::Array(
+                vals.into_iter()
+                    .map(|val| val.map_or(RespValue::Null, RespValue::from_value))
+                    .collect(),
+            ),
+            Err(why) => error(why),
+        },
+        ("MSET", pairs) if !pairs.is_empty() && pairs.len().is_multiple_of(2) => {
+            let pairs = pairs
+                .chunks(2)
+                .map(|pair| (pair[0].to_string(), Value::from(pair[1].as_str())))
+                .collect();
+            match db.mset(&session.table, pairs) {
+                Ok(()) => RespValue::Simple("OK".to_string()),
+                Err(why) => error(why),
+            }
+        }
+        ("KEYS", [pattern]) => {
+            let pattern = match KeyPattern::parse(pattern) {
+                Ok(pattern) => pattern,
+                Err(why) => return parse_error(why),
+            };
+            match db.keys(&session.table, Some(&pattern), None) {
+                Ok(keys) => RespValue::Array(keys.map(|key| RespValue::bulk(key)).collect()),
+                Err(why) => error(why),
+            }
+        }
+        ("SCAN", [cursor, options @ ..]) if Cursor::parse(cursor).is_ok() => {
+            let (pattern, count) = match parse_scan_options(options) {
+                Ok(options) => options,
+                Err(why) => return parse_error(why),
+            };
+            db.record_command("SCAN");
+            let cursor = Cursor::parse(cursor).unwrap_or_default();
+            let count = count.unwrap_or(DEFAULT_SCAN_COUNT);
+            match db.scan(&session.table, &cursor, pattern.as_ref(), count) {
+                Ok(page) => RespValue::Array(vec![
+                    RespValue::bulk(&page.cursor.to_string()),
+                    RespValue::Array(
+                        page.entries
+                            .into_iter()
+                            .map(|(key, _)| RespValue::bulk(key))
+                            .collect(),
+                    ),
+                ]),
+                Err(why) => error(why),
+            }
+        }
+        ("EXISTS", keys) if !keys.is_empty() => {
+            let found = keys
+                .iter()
+                .filter(|key| db.get(&session.table, key).is_ok())
+                .count();
+            RespValue::Integer(found as i64)
+        }
+        //one of ours that none of the arms above took, so the arguments were wrong
+        (name, _) if ANSWERED_HERE.contains(&name) => RespValue::Error(format!(
+            "ERR wrong number of arguments for '{}' command",
+            args[0].to_lowercase()
+        )),
+        //arguments arrive unquoted, so values are typed just like bare REPL tokens
+        _ => match parse_tokens(&args.iter().cloned().map(Token::Bare).collect::<Vec<_>>()) {
+            //every connection shares the one database, so a transaction
+            //would swallow other clients' writes
+            Ok(Command::Begin | Command::Commit | Command::Rollback) => {
+                RespValue::Error("ERR transactions are not supported over the network".to_string())
+            }
+            Ok(cmd) => match db.execute(cmd) {
+                Ok(result) => RespValue::from_result(&result),
+                Err(why) => error(why),
+            },
+            Err(why) => parse_error(why),
+        },
+    }
+}
+
+///HELLO [protover [AUTH user pass] [SETNAME name]]
+fn hello(args: &[String], session: &mut Session) -> RespValue {
+    if let Some(version) = args.first() {
+        session.protocol = match version.as_str() {
+            "2" => Protocol::Resp2,
+            "3" => Protocol::Resp3,
+            _ => {
+                return RespValue::Error("NOPROTO unsupported protocol version".to_string());
+            }
+        };
+    }
+    let proto = match session.protocol {
+        Protocol::Resp2 => 2,
+        Protocol::Resp3 => 3,
+    };
+    RespValue::Map(vec![
+        (RespValue::bulk("server"), RespValue::bulk("rusty_db")),
+        (
+            RespValue::bulk("version"),
+            RespValue::bulk(env!("CARGO_PKG_VERSION")),
+        ),
+        (RespValue::bulk("proto"), RespValue::Integer(proto)),
+        (RespValue::bulk("mode"), RespValue::bulk("standalone")),
+        (RespValue::bulk("role"), RespValue::bulk("master")),
+        (RespValue::bulk("modules"), RespValue::Array(Vec::new())),
+    ])
+}
+
+fn counter_reply(result: Result<i64>) -> RespValue {
+    match result {
+        Ok(val) => RespValue::Integer(val),
+        Err(why) => error(why),
+    }
+}
+
+///Error replies start with the error's code, where redis would say ERR
+fn error(why: RustyDbErr) -> RespValue {
+    RespValue::Error(format!("{} {}", why.code(), why))
+}
+
+fn parse_error(why: ParseError) -> RespValue {
+    RespValue::Error(format!("{} {}", why.code(), why))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Read, Write},
+    };
+
+    use super::*;
+
+    fn send(stream: &mut TcpStream, request: &[u8], expected: &[u8]) {
+        stream.write_all(request).unwrap();
+        let mut reply = vec![0; expected.len()];
+        stream.read_exact(&mut reply).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&reply),
+            String::from_utf8_lossy(expected)
+        );
+    }
+
+    #[test]
+    fn test_server_round_trip() -> Result<()> {
+        let path = "/tmp/rusty_db_server.bin";
+        fs::remove_file(path).ok();
+        fs::remove_file(format!("{}.wal", path)).ok();
+        let db = RustyDb::new(path)?;
+        let server = Server::bind(db, "127.0.0.1:0")?;
+        let addr = server.local_addr()?;
+        thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        send(&mut stream, b"PING\r\n", b"+PONG\r\n");
+        send(
+            &mut stream,
+            b"*3\r\n$3\r\nSET\r\n$2\r\nk1\r\n$5\r\nhello\r\n",
+            b"+OK\r\n",
+        );
+        send(
+            &mut stream,
+            b"*2\r\n$3\r\nGET\r\n$2\r\nk1\r\n",
+            b"$5\r\nhello\r\n",
+        );
+        send(&mut stream, b"GET missing\r\n", b"$-1\r\n");
+        let mut session = Session {
+            protocol: Protocol::Resp2,
+            table: DEFAULT_TABLE.to_string(),
+        };
+        let hello_reply = hello(&["3".to_string()], &mut session).encode(Protocol::Resp3);
+        send(&mut stream, b"HELLO 3\r\n", &hello_reply);
+        send(&mut stream, b"GET missing\r\n", b"_\r\n");
+        send(
+            &mut stream,
+            b"CREATE users\r\n",
+            b"+Created table users\r\n",
+        );
+        send(&mut stream, b"SELECT users\r\n", b"+OK\r\n");
+        send(&mut stream, b"GET k1\r\n", b"_\r\n");
+        send(&mut stream, b"DEL k1 k2\r\n", b":0\r\n");
+        send(&mut stream, b"SELECT default\r\n", b"+OK\r\n");
+        send(
+            &mut stream,
+            b"SELECT nope\r\n",
+            b"-TABLE_NOT_FOUND Table not found: nope\r\n",
+        );
+        send(
+            &mut stream,
+            b"DROP\r\n",
+            b"-WRONG_ARGUMENTS Wrong number of arguments: DROP requires 1 arguments,table_name! actual-> 1\r\n",
+        );
+        send(&mut stream, b"DEL k1 k2\r\n", b":1\r\n");
+        send(&mut stream, b"SET k1 v EX 100\r\n", b"+OK\r\n");
+        send(&mut stream, b"TTL k1\r\n", b":100\r\n");
+        send(&mut stream, b"PERSIST k1\r\n", b":1\r\n");
+        send(&mut stream, b"TTL k1\r\n", b":-1\r\n");
+        send(&mut stream, b"TTL missing\r\n", b":-2\r\n");
+        send(&mut stream, b"EXPIRE missing 10\r\n", b":0\r\n");
+        send(&mut stream, b"SET k1 v2 NX\r\n", b"_\r\n");
+        send(&mut stream, b"SETNX k1 v2\r\n", b":0\r\n");
+        send(&mut stream, b"SETNX k2 v2\r\n", b":1\r\n");
+        //values stay strings, so "1" doesn't match the int CAS would parse it as
+        send(&mut stream, b"SET n 1\r\n", b"+OK\r\n");
+        send(&mut stream, b"CAS n 2 3\r\n", b"_\r\n");
+        send(&mut stream, b"CAS n 1 3\r\n", b"+OK\r\n");
+        send(&mut stream, b"GET n\r\n", b"$1\r\n3\r\n");
+        send(
+            &mut stream,
+            b"CAS n 3\r\n",
+            b"-ERR wrong number of arguments for 'cas' command\r\n",
+        );
+        send(&mut stream, b"DEL n\r\n", b":1\r\n");
+        send(
+            &mut stream,
+            b"GET a b\r\n",
+            b"-ERR wrong number of arguments for 'get' command\r\n",
+        );
+        send(&mut stream, b"MSET a 1 b 2\r\n", b"+OK\r\n");
+        send(
+            &mut stream,
+            b"MGET a missing b\r\n",
+            b"*3\r\n$1\r\n1\r\n_\r\n$1\r\n2\r\n",
+        );
+        send(
+            &mut stream,
+            b"KEYS [ab]\r\n",
+            b"*2\r\n$1\r\na\r\n$1\r\nb\r\n",
+        );
+        send(
+            &mut stream,
+            b"SCAN 0 MATCH [ab] COUNT 1\r\n",
+            b"*2\r\n$3\r\n161\r\n*1\r\n$1\r\na\r\n",
+        );
+        send(
+            &mut stream,
+            b"SCAN 161 MATCH [ab]\r\n",
+            b"*2\r\n$1\r\n0\r\n*1\r\n$1\r\nb\r\n",
+        );
+        send(&mut stream, b"COUNT default\r\n", b":4\r\n");
+        send(
+            &mut stream,
+            b"PREFIX default k\r\n",
+            b"%2\r\n$2\r\nk1\r\n$1\r\nv\r\n$2\r\nk2\r\n$2\r\nv2\r\n",
+        );
+        send(
+            &mut stream,
+            b"LIST\r\n",
+            b"*2\r\n$7\r\ndefault\r\n$5\r\nusers\r\n",
+        );
+        send(&mut stream, b"DEL a b missing\r\n", b":2\r\n");
+        send(&mut stream, b"INCR hits\r\n", b":1\r\n");
+        send(&mut stream, b"INCRBY hits 10\r\n", b":11\r\n");
+        send(&mut stream, b"DECR hits\r\n", b":10\r\n");
+        send(&mut stream, b"INCRBYFLOAT hits 0.5\r\n", b"$4\r\n10.5\r\n");
+        send(
+            &mut stream,
+            b"BEGIN\r\n",
+            b"-ERR transactions are not supported over the network\r\n",
+        );
+        send(&mut stream, b"QUIT\r\n", b"+OK\r\n");
+
+        fs::remove_file(path).ok();
+        fs::remove_file(format!("{}.wal", path)).ok();
+        Ok(())
+    }
+}
@@ -0,0 +1,718 @@
+//! A small SQL front end over the key/value tables.
+//!
+//! Every table reads as if it had two columns, `key` (text, the primary key) and
+//! `value`. Supported statements:
+//!
+//! ```text
+//! SELECT * | key, value | key | value | COUNT(*) FROM table [WHERE ...] [LIMIT n]
+//! INSERT INTO table [(key, value)] VALUES ('k1', 1), ('k2', 'two')
+//! UPDATE table SET value = 42 [WHERE ...]
+//! DELETE FROM table [WHERE ...]
+//! ```
+//!
+//! A WHERE clause is conditions joined by AND, each `column op literal` with op one
+//! of `= != <> < <= > >= LIKE`. Conditions on the key narrow the range of keys read.
+
+use std::{
+    cmp::Ordering,
+    fmt::Display,
+    ops::{Bound, Range},
+};
+
+use crate::{err_types::ParseError, pattern::KeyPattern, value::Value};
+
+///A parsed SQL statement, run with `RustyDb::execute` as [`crate::Command::Sql`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Select {
+        table: String,
+        projection: Projection,
+        filter: Vec<Condition>,
+        limit: Option<usize>,
+    },
+    ///Fails without writing anything if any of the keys exists
+    Insert {
+        table: String,
+        rows: Vec<(String, Value)>,
+    },
+    ///Changes values only, keys keep their expiry
+    Update {
+        table: String,
+        value: Value,
+        filter: Vec<Condition>,
+    },
+    Delete {
+        table: String,
+        filter: Vec<Condition>,
+    },
+}
+
+impl Statement {
+    ///The statement's keyword, used to count commands
+    pub fn name(&self) -> &'static str {
+        match self {
+            Statement::Select { .. } => "SELECT",
+            Statement::Insert { .. } => "INSERT",
+            Statement::Update { .. } => "UPDATE",
+            Statement::Delete { .. } => "DELETE",
+        }
+    }
+}
+
+///What a SELECT returns
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    ///`*` or both columns
+    Rows,
+    Keys,
+    Values,
+    ///`COUNT(*)`
+    Count,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Column {
+    Key,
+    Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    ///`%` matches any run of characters, `_` any one
+    Like,
+}
+
+///One `column op literal` test of a WHERE clause. Conditions on the key always
+///compare against text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub column: Column,
+    pub op: Operator,
+    pub operand: Value,
+}
+
+impl Condition {
+    pub fn matches(&self, key: &str, val: &Value) -> bool {
+        let key = Value::Str(key.to_string());
+        let subject = match self.column {
+            Column::Key => &key,
+            Column::Value => val,
+        };
+        let order = compare(subject, &self.operand);
+        match self.op {
+            Operator::Eq => order == Some(Ordering::Equal),
+            Operator::NotEq => order != Some(Ordering::Equal),
+            Operator::Lt => order == Some(Ordering::Less),
+            Operator::LtEq => matches!(order, Some(Ordering::Less | Ordering::Equal)),
+            Operator::Gt => order == Some(Ordering::Greater),
+            Operator::GtEq => matches!(order, Some(Ordering::Greater | Ordering::Equal)),
+            Operator::Like => match (subject, &self.operand) {
+                (Value::Str(text), Value::Str(pattern)) => KeyPattern::like(pattern).matches(text),
+                _ => false,
+            },
+        }
+    }
+
+    ///The keys this condition can match, as bounds on the key
+    fn key_bounds(&self) -> (Bound<String>, Bound<String>) {
+        let (Column::Key, Value::Str(operand)) = (self.column, &self.operand) else {
+            return (Bound::Unbounded, Bound::Unbounded);
+        };
+        let operand = operand.clone();
+        match self.op {
+            Operator::Eq => (Bound::Included(operand.clone()), Bound::Included(operand)),
+            Operator::Lt => (Bound::Unbounded, Bound::Excluded(operand)),
+            Operator::LtEq => (Bound::Unbounded, Bound::Included(operand)),
+            Operator::Gt => (Bound::Excluded(operand), Bound::Unbounded),
+            Operator::GtEq => (Bound::Included(operand), Bound::Unbounded),
+            Operator::Like => {
+                let prefix: String = operand.chars().take_while(|c| !"%_".contains(*c)).collect();
+                let end = prefix_end(&prefix).map_or(Bound::Unbounded, Bound::Excluded);
+                (Bound::Included(prefix), end)
+            }
+            Operator::NotEq => (Bound::Unbounded, Bound::Unbounded),
+        }
+    }
+}
+
+///The range of keys that can match every condition of a WHERE clause, for
+///`RustyDb::range`. Rows in it still have to be checked against each condition.
+pub fn key_range(filter: &[Condition]) -> (Bound<String>, Bound<String>) {
+    let mut range = (Bound::Unbounded, Bound::Unbounded);
+    for (start, end) in filter.iter().map(Condition::key_bounds) {
+        //any of the bounds is right as they all have to hold, keep the tightest
+        if bound_key(&start) > bound_key(&range.0) {
+            range.0 = start;
+        }
+        let tighter_end = match (bound_key(&end), bound_key(&range.1)) {
+            (Some(end), Some(current)) => end < current,
+            (end, current) => end.is_some() && current.is_none(),
+        };
+        if tighter_end {
+            range.1 = end;
+        }
+    }
+    range
+}
+
+fn bound_key(bound: &Bound<String>) -> Option<&str> {
+    match bound {
+        Bound::Included(key) | Bound::Excluded(key) => Some(key),
+        Bound::Unbounded => None,
+    }
+}
+
+///The first string after every string starting with `prefix`, `None` if there is
+///none or the prefix is empty
+fn prefix_end(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(last) = chars.pop() {
+        if let Some(next) = char::from_u32(last as u32 + 1) {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+///Order two values, numbers by value whatever their type, `None` when they don't compare
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::Int(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+        (Value::Float(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+        (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Bytes(a), Value::Bytes(b)) => Some(a.cmp(b)),
+        (a, b) if a == b => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+///Whether `input` looks like SQL rather than a command, by its first word
+pub fn is_sql(input: &str) -> bool {
+    let first = input.split_whitespace().next().unwrap_or_default();
+    ["SELECT", "INSERT", "UPDATE", "DELETE"]
+        .iter()
+        .any(|keyword| first.eq_ignore_ascii_case(keyword))
+}
+
+///Parse one SQL statement, optionally ending in `;`. Errors carry the span of the
+///offending token.
+pub fn parse(input: &str) -> Result<Statement, ParseError> {
+    let mut parser = Parser {
+        tokens: lex(input)?,
+        pos: 0,
+        end: input.len(),
+    };
+    let statement = match parser.keyword_any(&["SELECT", "INSERT", "UPDATE", "DELETE"])? {
+        "SELECT" => parser.select()?,
+        "INSERT" => parser.insert()?,
+        "UPDATE" => parser.update()?,
+        _ => parser.delete()?,
+    };
+    parser.accept_symbol(";");
+    match parser.peek() {
+        None => Ok(statement),
+        Some(_) => Err(parser.error("Expected the end of the statement")),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SqlToken {
+    ///A keyword or name
+    Word(String),
+    ///A `"quoted"` name, never a keyword
+    Name(String),
+    Str(String),
+    Number(Value),
+    Symbol(&'static str),
+}
+
+impl Display for SqlToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SqlToken::Word(word) => write!(f, "{}", word),
+            SqlToken::Name(name) => write!(f, "\"{}\"", name),
+            SqlToken::Str(text) => write!(f, "'{}'", text),
+            SqlToken::Number(number) => write!(f, "{}", number),
+            SqlToken::Symbol(symbol) => write!(f, "'{}'", symbol),
+        }
+    }
+}
+
+const SYMBOLS: &[&str] = &[
+    "<=", ">=", "<>", "!=", "=", "<", ">", ",", "(", ")", "*", ";",
+];
+
+fn lex(input: &str) -> Result<Vec<(SqlToken, Range<usize>)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut rest = input.char_indices().peekable();
+    while let Some(&(start, c)) = rest.peek() {
+        if c.is_whitespace() {
+            rest.next();
+            continue;
+        }
+        let token = if c == '\'' || c == '"' {
+            rest.next();
+            //a doubled quote stands for itself
+            let mut text = String::new();
+            loop {
+                match rest.next() {
+                    Some((_, q)) if q == c => {
+                        if rest.next_if(|&(_, next)| next == c).is_none() {
+                            break;
+                        }
+                        text.push(c);
+                    }
+                    Some((_, other)) => text.push(other),
+                    None => {
+                        return Err(
+                            ParseError::invalid_token(format!("Unterminated {} quote", c))
+                                .at_span(start..input.len()),
+                        );
+                    }
+                }
+            }
+            if c == '\'' {
+                SqlToken::Str(text)
+            } else {
+                SqlToken::Name(text)
+            }
+        } else if c.is_ascii_digit() || c == '-' || c == '.' {
+            let mut text = String::new();
+            while let Some((_, c)) = rest
+                .next_if(|&(_, c)| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '+')
+            {
+                text.push(c);
+            }
+            match Value::from_bare(&text) {
+                Ok(number @ (Value::Int(_) | Value::Float(_))) => SqlToken::Number(number),
+                _ => {
+                    return Err(
+                        ParseError::invalid_token(format!("Invalid number: {}", text))
+                            .at_span(start..start + text.len()),
+                    );
+                }
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let mut word = String::new();
+            while let Some((_, c)) = rest.next_if(|&(_, c)| c.is_alphanumeric() || c == '_') {
+                word.push(c);
+            }
+            SqlToken::Word(word)
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| input[start..].starts_with(**symbol))
+                .ok_or_else(|| {
+                    ParseError::invalid_token(format!("Unexpected '{}'", c))
+                        .at_span(start..start + c.len_utf8())
+                })?;
+            for _ in 0..symbol.len() {
+                rest.next();
+            }
+            SqlToken::Symbol(symbol)
+        };
+        let end = rest.peek().map_or(input.len(), |&(end, _)| end);
+        tokens.push((token, start..end));
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(SqlToken, Range<usize>)>,
+    pos: usize,
+    ///length of the input, where errors about a missing token point
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&SqlToken> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    ///An error pointing at the current token
+    fn error(&self, message: &str) -> ParseError {
+        let (found, span) = match self.tokens.get(self.pos) {
+            Some((token, span)) => (token.to_string(), span.clone()),
+            None => ("the end of the statement".to_string(), self.end..self.end),
+        };
+        ParseError::invalid_command(format!("{}, found {}", message, found)).at_span(span)
+    }
+
+    fn accept_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(SqlToken::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if self.accept_keyword(keyword) {
+            return Ok(());
+        }
+        Err(self.error(&format!("Expected {}", keyword)))
+    }
+
+    fn keyword_any(&mut self, keywords: &[&'static str]) -> Result<&'static str, ParseError> {
+        keywords
+            .iter()
+            .find(|keyword| self.accept_keyword(keyword))
+            .copied()
+            .ok_or_else(|| self.error(&format!("Expected {}", keywords.join(" or "))))
+    }
+
+    fn accept_symbol(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(SqlToken::Symbol(found)) if *found == symbol) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn symbol(&mut self, symbol: &str) -> Result<(), ParseError> {
+        if self.accept_symbol(symbol) {
+            return Ok(());
+        }
+        Err(self.error(&format!("Expected '{}'", symbol)))
+    }
+
+    ///A table name, bare or `"quoted"`
+    fn name(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(SqlToken::Word(name) | SqlToken::Name(name)) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.error("Expected a table name")),
+        }
+    }
+
+    fn column(&mut self) -> Result<Column, ParseError> {
+        match self.keyword_any(&["KEY", "VALUE"]) {
+            Ok("KEY") => Ok(Column::Key),
+            Ok(_) => Ok(Column::Value),
+            Err(_) => Err(self.error("Expected a column, key or value")),
+        }
+    }
+
+    ///A string, number, TRUE, FALSE or NULL
+    fn literal(&mut self) -> Result<Value, ParseError> {
+        let value = match self.peek() {
+            Some(SqlToken::Str(text)) => Value::Str(text.clone()),
+            Some(SqlToken::Number(number)) => number.clone(),
+            Some(SqlToken::Word(word)) => match word.to_uppercase().as_str() {
+                "TRUE" => Value::Bool(true),
+                "FALSE" => Value::Bool(false),
+                "NULL" => Value::Null,
+                _ => return Err(self.error("Expected a value")),
+            },
+            _ => return Err(self.error("Expected a value")),
+        };
+        self.pos += 1;
+        Ok(value)
+    }
+
+    ///A literal used as a key, numbers are read as their text
+    fn key(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(SqlToken::Number(number)) => {
+                let key = number.to_string();
+                self.pos += 1;
+                Ok(key)
+            }
+            Some(SqlToken::Str(_)) => match self.literal()? {
+                Value::Str(key) => Ok(key),
+                _ => unreachable!("string tokens are string literals"),
+            },
+            _ => Err(self.error("Expected a key, keys are text")),
+        }
+    }
+
+    fn select(&mut self) -> Result<Statement, ParseError> {
+        let projection = if self.accept_symbol("*") {
+            Projection::Rows
+        } else if self.accept_keyword("COUNT") {
+            self.symbol("(")?;
+            self.symbol("*")?;
+            self.symbol(")")?;
+            Projection::Count
+        } else {
+            let mut columns = vec![self.column()?];
+            while self.accept_symbol(",") {
+                columns.push(self.column()?);
+            }
+            match (
+                columns.contains(&Column::Key),
+                columns.contains(&Column::Value),
+            ) {
+                (true, true) => Projection::Rows,
+                (true, false) => Projection::Keys,
+                _ => Projection::Values,
+            }
+        };
+        self.keyword("FROM")?;
+        let table = self.name()?;
+        let filter = self.filter()?;
+        let limit = if self.accept_keyword("LIMIT") {
+            match self.peek() {
+                Some(SqlToken::Number(Value::Int(limit))) if *limit >= 0 => {
+                    let limit = *limit as usize;
+                    self.pos += 1;
+                    Some(limit)
+                }
+                _ => return Err(self.error("LIMIT expects a number")),
+            }
+        } else {
+            None
+        };
+        Ok(Statement::Select {
+            table,
+            projection,
+            filter,
+            limit,
+        })
+    }
+
+    fn insert(&mut self) -> Result<Statement, ParseError> {
+        self.keyword("INTO")?;
+        let table = self.name()?;
+        let mut columns = [Column::Key, Column::Value];
+        if self.accept_symbol("(") {
+            columns[0] = self.column()?;
+            self.symbol(",")?;
+            columns[1] = self.column()?;
+            if columns[0] == columns[1] {
+                self.pos -= 1;
+                return Err(self.error("INSERT needs both the key and value columns"));
+            }
+            self.symbol(")")?;
+        }
+        self.keyword("VALUES")?;
+        let mut rows = Vec::new();
+        loop {
+            self.symbol("(")?;
+            let row = if columns[0] == Column::Key {
+                let key = self.key()?;
+                self.symbol(",")?;
+                (key, self.literal()?)
+            } else {
+                let val = self.literal()?;
+                self.symbol(",")?;
+                (self.key()?, val)
+            };
+            self.symbol(")")?;
+            rows.push(row);
+            if !self.accept_symbol(",") {
+                return Ok(Statement::Insert { table, rows });
+            }
+        }
+    }
+
+    fn update(&mut self) -> Result<Statement, ParseError> {
+        let table = self.name()?;
+        self.keyword("SET")?;
+        if self.column()? == Column::Key {
+            self.pos -= 1;
+            return Err(self.error("Keys can't be updated, only values"));
+        }
+        self.symbol("=")?;
+        let value = self.literal()?;
+        let filter = self.filter()?;
+        Ok(Statement::Update {
+            table,
+            value,
+            filter,
+        })
+    }
+
+    fn delete(&mut self) -> Result<Statement, ParseError> {
+        self.keyword("FROM")?;
+        let table = self.name()?;
+        let filter = self.filter()?;
+        Ok(Statement::Delete { table, filter })
+    }
+
+    ///An optional WHERE clause
+    fn filter(&mut self) -> Result<Vec<Condition>, ParseError> {
+        let mut filter = Vec::new();
+        if !self.accept_keyword("WHERE") {
+            return Ok(filter);
+        }
+        loop {
+            let column = self.column()?;
+            let op = if self.accept_keyword("LIKE") {
+                Operator::Like
+            } else {
+                let op = match self.peek() {
+                    Some(SqlToken::Symbol("=")) => Operator::Eq,
+                    Some(SqlToken::Symbol("!=" | "<>")) => Operator::NotEq,
+                    Some(SqlToken::Symbol("<")) => Operator::Lt,
+                    Some(SqlToken::Symbol("<=")) => Operator::LtEq,
+                    Some(SqlToken::Symbol(">")) => Operator::Gt,
+                    Some(SqlToken::Symbol(">=")) => Operator::GtEq,
+                    _ => return Err(self.error("Expected a comparison or LIKE")),
+                };
+                self.pos += 1;
+                op
+            };
+            let operand = match column {
+                Column::Key => Value::Str(self.key()?),
+                Column::Value => self.literal()?,
+            };
+            filter.push(Condition {
+                column,
+                op,
+                operand,
+            });
+            if !self.accept_keyword("AND") {
+                return Ok(filter);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_select() {
+        assert_eq!(
+            parse("SELECT key, value FROM users WHERE key LIKE 'a%' LIMIT 10"),
+            Ok(Statement::Select {
+                table: "users".to_string(),
+                projection: Projection::Rows,
+                filter: vec![Condition {
+                    column: Column::Key,
+                    op: Operator::Like,
+                    operand: Value::from("a%"),
+                }],
+                limit: Some(10),
+            })
+        );
+        assert_eq!(
+            parse("select count(*) from \"my table\" where value >= 1.5 and key <> 'x';"),
+            Ok(Statement::Select {
+                table: "my table".to_string(),
+                projection: Projection::Count,
+                filter: vec![
+                    Condition {
+                        column: Column::Value,
+                        op: Operator::GtEq,
+                        operand: Value::Float(1.5),
+                    },
+                    Condition {
+                        column: Column::Key,
+                        op: Operator::NotEq,
+                        operand: Value::from("x"),
+                    },
+                ],
+                limit: None,
+            })
+        );
+        let err = parse("SELECT key FROM users LIMIT ten").unwrap_err();
+        assert_eq!(err.span, Some(28..31));
+        assert_eq!(parse("SELECT * FROM").unwrap_err().span, Some(13..13));
+        assert!(parse("SELECT name FROM users").is_err());
+    }
+
+    #[test]
+    fn test_parse_writes() {
+        assert_eq!(
+            parse("INSERT INTO users (value, key) VALUES (1, 'a'), ('it''s', 2)"),
+            Ok(Statement::Insert {
+                table: "users".to_string(),
+                rows: vec![
+                    ("a".to_string(), Value::Int(1)),
+                    ("2".to_string(), Value::from("it's")),
+                ],
+            })
+        );
+        assert_eq!(
+            parse("UPDATE users SET value = NULL WHERE key = 'a'"),
+            Ok(Statement::Update {
+                table: "users".to_string(),
+                value: Value::Null,
+                filter: vec![Condition {
+                    column: Column::Key,
+                    op: Operator::Eq,
+                    operand: Value::from("a"),
+                }],
+            })
+        );
+        assert_eq!(
+            parse("DELETE FROM users"),
+            Ok(Statement::Delete {
+                table: "users".to_string(),
+                filter: Vec::new(),
+            })
+        );
+        assert!(parse("UPDATE users SET key = 'b'").is_err());
+        assert!(parse("INSERT INTO users VALUES ('a', 1) extra").is_err());
+        assert!(parse("DELETE FROM users WHERE key = 'a").is_err());
+    }
+
+    #[test]
+    fn test_conditions() {
+        let like = |pattern: &str, key: &str| {
+            Condition {
+                column: Column::Key,
+                op: Operator::Like,
+                operand: Value::from(pattern),
+            }
+            .matches(key, &Value::Null)
+        };
+        assert!(like("a%", "alice"));
+        assert!(!like("%c_", "bob cat"));
+        assert!(like("_o%", "bob"));
+        assert!(!like("a%", "bob"));
+        assert!(like("100%", "100%"));
+        assert!(!like("a*", "abc"));
+        //many wildcards against a long miss stays linear, not exponential
+        let text = "a".repeat(200);
+        assert!(!like(&format!("{}b", "%a".repeat(30)), &text));
+
+        let value_gt = Condition {
+            column: Column::Value,
+            op: Operator::Gt,
+            operand: Value::Int(1),
+        };
+        assert!(value_gt.matches("k", &Value::Float(1.5)));
+        assert!(!value_gt.matches("k", &Value::from("2")));
+
+        let filter = [
+            Condition {
+                column: Column::Key,
+                op: Operator::Like,
+                operand: Value::from("user:%"),
+            },
+            Condition {
+                column: Column::Key,
+                op: Operator::Gt,
+                operand: Value::from("user:5"),
+            },
+        ];
+        assert_eq!(
+            key_range(&filter),
+            (
+                Bound::Excluded("user:5".to_string()),
+                Bound::Excluded("user;".to_string())
+            )
+        );
+        assert!(is_sql("select * from t"));
+        assert!(!is_sql("DEL t k"));
+    }
+}
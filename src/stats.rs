@@ -0,0 +1,79 @@
+use std::{collections::BTreeMap, fmt::Display, time::Duration};
+
+use crate::table_meta::format_timestamp;
+
+///How many past checkpoints [`Stats::checkpoints`] remembers
+pub const CHECKPOINT_HISTORY_LEN: usize = 10;
+
+///One completed checkpoint
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckpointInfo {
+    ///unix millis the checkpoint finished
+    pub at: u64,
+    pub took: Duration,
+    ///WAL entries the snapshot replaced
+    pub wal_entries: usize,
+    pub snapshot_bytes: u64,
+}
+
+///Database wide figures, see `RustyDb::stats`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub tables: usize,
+    pub keys: usize,
+    ///keys with an expiry set
+    pub expiring_keys: usize,
+    pub snapshot_bytes: u64,
+    pub wal_bytes: u64,
+    ///WAL entries written since the last checkpoint
+    pub operations_since_checkpoint: usize,
+    pub unsynced_wal_entries: usize,
+    ///checkpoints since the database was opened
+    pub total_checkpoints: usize,
+    ///the most recent checkpoints, oldest first
+    pub checkpoints: Vec<CheckpointInfo>,
+    ///why the last automatic checkpoint failed, if the ones since haven't worked either
+    pub last_checkpoint_error: Option<String>,
+    ///time since the database was opened
+    pub uptime: Duration,
+    ///times each command has been run since the database was opened
+    pub commands: BTreeMap<String, u64>,
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "uptime: {}s", self.uptime.as_secs())?;
+        writeln!(f, "tables: {}", self.tables)?;
+        writeln!(f, "keys: {}", self.keys)?;
+        writeln!(f, "expiring keys: {}", self.expiring_keys)?;
+        writeln!(f, "snapshot size: {} bytes", self.snapshot_bytes)?;
+        writeln!(f, "wal size: {} bytes", self.wal_bytes)?;
+        writeln!(
+            f,
+            "operations since checkpoint: {}",
+            self.operations_since_checkpoint
+        )?;
+        writeln!(f, "unsynced wal entries: {}", self.unsynced_wal_entries)?;
+        write!(f, "checkpoints: {}", self.total_checkpoints)?;
+        for checkpoint in self.checkpoints.iter().rev() {
+            write!(
+                f,
+                "\n  {} took {}ms, {} wal entries, {} byte snapshot",
+                format_timestamp(checkpoint.at),
+                checkpoint.took.as_millis(),
+                checkpoint.wal_entries,
+                checkpoint.snapshot_bytes
+            )?;
+        }
+        if let Some(why) = &self.last_checkpoint_error {
+            write!(f, "\nlast checkpoint error: {}", why)?;
+        }
+        if !self.commands.is_empty() {
+            write!(f, "\ncommands:")?;
+            for (command, count) in &self.commands {
+                write!(f, "\n  {}: {}", command, count)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use std::{fmt::Display, time::Duration};
+
+use bincode::{Decode, Encode};
+
+///Settings a table carries, changed with ALTER
+#[derive(Debug, Clone, Default, PartialEq, Encode, Decode)]
+pub struct TableOptions {
+    pub description: Option<String>,
+    ///expiry given to keys SET without one of their own
+    pub default_ttl: Option<Duration>,
+}
+
+///What the database tracks about a table besides its keys, kept in the snapshot
+#[derive(Debug, Clone, Default, PartialEq, Encode, Decode)]
+pub(crate) struct TableMeta {
+    ///unix millis, `None` for tables from before metadata was kept
+    pub created_at: Option<u64>,
+    pub modified_at: Option<u64>,
+    pub options: TableOptions,
+}
+
+///A table's metadata plus figures worked out from its contents, see `RustyDb::table_info`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableInfo {
+    pub name: String,
+    ///unix millis
+    pub created_at: Option<u64>,
+    ///unix millis of the last write
+    pub modified_at: Option<u64>,
+    pub keys: usize,
+    ///rough in-memory size of the keys and values, not the size on disk
+    pub approx_bytes: usize,
+    pub options: TableOptions,
+}
+
+impl Display for TableInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let time = |at: Option<u64>| at.map_or("unknown".to_string(), format_timestamp);
+        writeln!(f, "table: {}", self.name)?;
+        if let Some(description) = &self.options.description {
+            writeln!(f, "description: {}", description)?;
+        }
+        writeln!(f, "created: {}", time(self.created_at))?;
+        writeln!(f, "modified: {}", time(self.modified_at))?;
+        writeln!(f, "keys: {}", self.keys)?;
+        writeln!(f, "approx size: {} bytes", self.approx_bytes)?;
+        match self.options.default_ttl {
+            Some(ttl) => write!(f, "default ttl: {}s", ttl.as_secs()),
+            None => write!(f, "default ttl: none"),
+        }
+    }
+}
+
+///Unix millis as `YYYY-MM-DD HH:MM:SS UTC`
+pub(crate) fn format_timestamp(millis: u64) -> String {
+    let secs = millis / 1000;
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+    //civil date from days since the epoch, see Howard Hinnant's date algorithms
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(951_782_400_000), "2000-02-29 00:00:00 UTC");
+        assert_eq!(
+            format_timestamp(1_792_325_045_123),
+            "2026-10-18 12:04:05 UTC"
+        );
+    }
+}
@@ -0,0 +1,454 @@
+use std::ops::Range;
+
+use crate::{err_types::ParseError, value::Value};
+
+///A token of a command line
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    ///Unquoted text, as a value it may be a number, bool, null, list or map
+    Bare(String),
+    ///Text that was at least partly quoted, as a value it is always a string
+    Quoted(String),
+    ///A hex or base64 literal
+    Bytes(Vec<u8>),
+}
+
+impl Token {
+    ///The token as a name or keyword, binary literals are read as (lossy) utf8
+    pub fn text(&self) -> String {
+        match self {
+            Token::Bare(text) | Token::Quoted(text) => text.clone(),
+            Token::Bytes(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+        }
+    }
+
+    ///The token as a value to store
+    pub fn to_value(&self) -> Result<Value, ParseError> {
+        match self {
+            Token::Bare(text) => Value::from_bare(text),
+            Token::Quoted(text) => Ok(Value::Str(text.clone())),
+            Token::Bytes(bytes) => Ok(Value::Bytes(bytes.clone())),
+        }
+    }
+}
+
+///Split a command line into tokens.
+///
+///Tokens are separated by whitespace, with these exceptions:
+/// - `"double quoted"` text keeps its whitespace and understands backslash escapes
+/// - `'single quoted'` text is taken literally
+/// - `x'48656c6c6f'` is a hex literal and `b64'aGVsbG8='` a base64 literal
+/// - outside quotes a backslash escapes the next character, eg `Alice\ Smith`
+/// - a token starting with `[` or `{` runs to the matching bracket, so list and
+///   map literals like `[1, "a b"]` are one token, kept verbatim for [`Value::from_bare`]
+///
+///Quoted and unquoted pieces with no whitespace between them join into one token,
+///so `user:"a b"` is the single token `user:a b`.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    Ok(tokenize_spanned(input)?
+        .into_iter()
+        .map(|(token, _)| token)
+        .collect())
+}
+
+///[`tokenize`], also giving the byte range of the input each token came from.
+///Errors carry the span of the token that couldn't be read.
+pub fn tokenize_spanned(input: &str) -> Result<Vec<(Token, Range<usize>)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars();
+    //None until we see something that belongs to a token, so `""` still counts
+    let mut current: Option<Token> = None;
+    //where the current token started
+    let mut start = 0;
+    //bracket nesting of the list or map literal we are in
+    let mut depth = 0;
+
+    while let Some(c) = chars.next() {
+        let at = input.len() - chars.as_str().len() - c.len_utf8();
+        if depth == 0 && c.is_whitespace() {
+            if let Some(token) = current.take() {
+                tokens.push((token, start..at));
+            }
+            continue;
+        }
+        if depth == 0 && current.is_none() {
+            start = at;
+        }
+        read_char(c, &mut chars, &mut current, &mut depth).map_err(|e| {
+            //the rest of the word is as far as we can tell the bad token runs
+            let read = input.len() - chars.as_str().len();
+            let end = input[read..]
+                .find(char::is_whitespace)
+                .map_or(input.len(), |len| read + len);
+            e.at_span(start..end)
+        })?;
+    }
+    if depth > 0 {
+        return Err(ParseError::invalid_token("Unclosed bracket in list or map")
+            .at_span(start..input.len()));
+    }
+    if let Some(token) = current {
+        tokens.push((token, start..input.len()));
+    }
+    Ok(tokens)
+}
+
+///Add the non whitespace character `c` (or an escape or quoted section starting
+///with it) to the token being built
+fn read_char(
+    c: char,
+    chars: &mut std::str::Chars,
+    current: &mut Option<Token>,
+    depth: &mut usize,
+) -> Result<(), ParseError> {
+    if *depth > 0 {
+        let text = token_text(current, false)?;
+        text.push(c);
+        match c {
+            '[' | '{' => *depth += 1,
+            ']' | '}' => *depth -= 1,
+            '"' | '\'' => copy_quoted(chars, c, text)?,
+            _ => {}
+        }
+        return Ok(());
+    }
+    match c {
+        '[' | '{' if current.is_none() => {
+            *depth = 1;
+            token_text(current, false)?.push(c);
+        }
+        '"' => read_double_quoted(chars, token_text(current, true)?)?,
+        '\'' => {
+            //prefix of a binary literal?
+            let prefix = match &current {
+                Some(Token::Bare(prefix)) => prefix.as_str(),
+                _ => "",
+            };
+            match prefix {
+                "x" | "X" => {
+                    let literal = read_single_quoted(chars)?;
+                    *current = Some(Token::Bytes(decode_hex(&literal)?));
+                }
+                "b64" | "B64" => {
+                    let literal = read_single_quoted(chars)?;
+                    *current = Some(Token::Bytes(decode_base64(&literal)?));
+                }
+                _ => {
+                    let literal = read_single_quoted(chars)?;
+                    token_text(current, true)?.push_str(&literal);
+                }
+            }
+        }
+        '\\' => {
+            let escaped = chars
+                .next()
+                .ok_or_else(|| ParseError::invalid_token("Trailing backslash".to_string()))?;
+            token_text(current, false)?.push(escaped);
+        }
+        c => token_text(current, false)?.push(c),
+    }
+    Ok(())
+}
+
+///The text of the token being built, starting one if needed. Quoting any part of a
+///token makes it a quoted token.
+fn token_text(current: &mut Option<Token>, quoted: bool) -> Result<&mut String, ParseError> {
+    let token = current.take().unwrap_or(Token::Bare(String::new()));
+    let token = match token {
+        Token::Bare(text) if quoted => Token::Quoted(text),
+        Token::Bytes(_) => {
+            return Err(ParseError::invalid_token(
+                "Binary literals can't be joined with other text".to_string(),
+            ));
+        }
+        token => token,
+    };
+    match current.insert(token) {
+        Token::Bare(text) | Token::Quoted(text) => Ok(text),
+        Token::Bytes(_) => unreachable!("rejected above"),
+    }
+}
+
+///Copy a quoted section of a list or map literal verbatim, up to its closing quote
+fn copy_quoted(
+    chars: &mut std::str::Chars,
+    quote: char,
+    text: &mut String,
+) -> Result<(), ParseError> {
+    while let Some(c) = chars.next() {
+        text.push(c);
+        if c == quote {
+            return Ok(());
+        }
+        if c == '\\' && quote == '"' {
+            text.extend(chars.next());
+        }
+    }
+    Err(ParseError::invalid_token(format!(
+        "Unterminated {} quote",
+        quote
+    )))
+}
+
+pub(crate) fn read_double_quoted(
+    chars: &mut std::str::Chars,
+    token: &mut String,
+) -> Result<(), ParseError> {
+    loop {
+        match chars.next() {
+            None => {
+                return Err(ParseError::invalid_token(
+                    "Unterminated double quote".to_string(),
+                ));
+            }
+            Some('"') => return Ok(()),
+            Some('\\') => token.push(read_escape(chars)?),
+            Some(c) => token.push(c),
+        }
+    }
+}
+
+pub(crate) fn read_single_quoted(chars: &mut std::str::Chars) -> Result<String, ParseError> {
+    let mut literal = String::new();
+    loop {
+        match chars.next() {
+            None => {
+                return Err(ParseError::invalid_token(
+                    "Unterminated single quote".to_string(),
+                ));
+            }
+            Some('\'') => return Ok(literal),
+            Some(c) => literal.push(c),
+        }
+    }
+}
+
+///Read the character(s) after a backslash inside double quotes
+fn read_escape(chars: &mut std::str::Chars) -> Result<char, ParseError> {
+    let c = chars
+        .next()
+        .ok_or_else(|| ParseError::invalid_token("Unterminated escape".to_string()))?;
+    match c {
+        'n' => Ok('\n'),
+        't' => Ok('\t'),
+        'r' => Ok('\r'),
+        '0' => Ok('\0'),
+        '\\' | '"' | '\'' => Ok(c),
+        'x' => {
+            let hex: String = chars.by_ref().take(2).collect();
+            let code = u8::from_str_radix(&hex, 16)
+                .map_err(|_| ParseError::invalid_token(format!("Invalid escape: \\x{}", hex)))?;
+            if !code.is_ascii() {
+                return Err(ParseError::invalid_token(format!(
+                    "Escape \\x{} is not ascii, use \\u{{..}} instead",
+                    hex
+                )));
+            }
+            Ok(code as char)
+        }
+        'u' => {
+            if chars.next() != Some('{') {
+                return Err(ParseError::invalid_token(
+                    "Expected \\u{XXXX} unicode escape".to_string(),
+                ));
+            }
+            let hex: String = chars.by_ref().take_while(|c| *c != '}').collect();
+            u32::from_str_radix(&hex, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| ParseError::invalid_token(format!("Invalid escape: \\u{{{}}}", hex)))
+        }
+        other => Err(ParseError::invalid_token(format!(
+            "Unknown escape: \\{}",
+            other
+        ))),
+    }
+}
+
+pub(crate) fn decode_hex(literal: &str) -> Result<Vec<u8>, ParseError> {
+    if !literal.len().is_multiple_of(2) {
+        return Err(ParseError::invalid_token(format!(
+            "Hex literal needs an even number of digits: {}",
+            literal
+        )));
+    }
+    (0..literal.len())
+        .step_by(2)
+        .map(|i| {
+            literal
+                .get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| {
+                    ParseError::invalid_token(format!("Invalid hex literal: {}", literal))
+                })
+        })
+        .collect()
+}
+
+pub(crate) fn decode_base64(literal: &str) -> Result<Vec<u8>, ParseError> {
+    let invalid = || ParseError::invalid_token(format!("Invalid base64 literal: {}", literal));
+    let trimmed = literal.trim_end_matches('=');
+    let mut bytes = Vec::with_capacity(trimmed.len() * 3 / 4);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in trimmed.chars() {
+        let sextet = match c {
+            'A'..='Z' => c as u32 - 'A' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 26,
+            '0'..='9' => c as u32 - '0' as u32 + 52,
+            '+' | '-' => 62,
+            '/' | '_' => 63,
+            _ => return Err(invalid()),
+        };
+        buffer = (buffer << 6) | sextet;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    //leftover bits must be padding zeros, a single leftover sextet is never valid
+    if bits >= 6 || buffer != 0 {
+        return Err(invalid());
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::err_types::ParseErrorKind;
+
+    fn texts(input: &str) -> Vec<String> {
+        tokenize(input).unwrap().iter().map(Token::text).collect()
+    }
+
+    #[test]
+    fn test_tokenize_plain() {
+        assert_eq!(
+            texts("  SET users   u1 alice "),
+            vec!["SET", "users", "u1", "alice"]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_quotes() {
+        assert_eq!(
+            texts(r#"SET users u1 "Alice Smith" 'it is "raw"' """#),
+            vec!["SET", "users", "u1", "Alice Smith", "it is \"raw\"", ""]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_joins_adjacent_pieces() {
+        assert_eq!(texts(r#"user:"a b"'c'"#), vec!["user:a bc"]);
+    }
+
+    #[test]
+    fn test_tokenize_escapes() {
+        assert_eq!(
+            texts(r#""line1\nline2\t\"q\" \x41\u{e9}" a\ b"#),
+            vec!["line1\nline2\t\"q\" A\u{e9}", "a b"]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_binary_literals() {
+        assert_eq!(
+            tokenize("x'48656c6c6f' b64'aGVsbG8gd29ybGQ=' b64'aGk'").unwrap(),
+            vec![
+                Token::Bytes(b"Hello".to_vec()),
+                Token::Bytes(b"hello world".to_vec()),
+                Token::Bytes(b"hi".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_kinds() {
+        assert_eq!(
+            tokenize(r#"42 "42" n"42""#).unwrap(),
+            vec![
+                Token::Bare("42".to_string()),
+                Token::Quoted("42".to_string()),
+                Token::Quoted("n42".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_list_and_map_literals() {
+        assert_eq!(
+            texts(r#"SET t k [1, "a ] b", {x: [2, 3]}] EX 5"#),
+            vec!["SET", "t", "k", r#"[1, "a ] b", {x: [2, 3]}]"#, "EX", "5"]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_spans() {
+        let spans: Vec<_> = tokenize_spanned(r#"SET  "a b" x'00'"#)
+            .unwrap()
+            .into_iter()
+            .map(|(_, span)| span)
+            .collect();
+        assert_eq!(spans, [0..3, 5..10, 11..16]);
+        let err = tokenize_spanned(r#"SET k "open quote"#).unwrap_err();
+        assert_eq!(err.span, Some(6..17));
+        let err = tokenize_spanned("SET k x'zz' v").unwrap_err();
+        assert_eq!(err.span, Some(6..11));
+    }
+
+    #[test]
+    fn test_tokenize_errors() {
+        assert!(matches!(
+            tokenize(r#"SET t k "open"#),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidToken,
+                ..
+            })
+        ));
+        assert!(matches!(
+            tokenize("SET t k 'open"),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidToken,
+                ..
+            })
+        ));
+        assert!(matches!(
+            tokenize(r#""\q""#),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidToken,
+                ..
+            })
+        ));
+        assert!(matches!(
+            tokenize("x'abc'"),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidToken,
+                ..
+            })
+        ));
+        assert!(matches!(
+            tokenize("b64'a'"),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidToken,
+                ..
+            })
+        ));
+        assert!(matches!(
+            tokenize("[1, 2"),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidToken,
+                ..
+            })
+        ));
+        assert!(matches!(
+            tokenize("x'00'abc"),
+            Err(ParseError {
+                kind: ParseErrorKind::InvalidToken,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,398 @@
+use std::{collections::BTreeMap, fmt::Display, str::Chars};
+
+use bincode::{Decode, Encode};
+
+use crate::{
+    err_types::ParseError,
+    tokenizer::{decode_base64, decode_hex, read_double_quoted, read_single_quoted},
+};
+
+///A typed value stored against a key
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    ///Type an unquoted token. `null`, `true`, `false`, numbers, `[lists]` and `{maps}`
+    ///become their types, any other text is a string.
+    pub fn from_bare(text: &str) -> Result<Value, ParseError> {
+        match parse_literal(text) {
+            Ok(value) => Ok(value),
+            //clearly meant as a list or map, so don't quietly store it as text
+            Err(why) if text.starts_with(['[', '{']) => Err(why),
+            Err(_) => Ok(Value::Str(text.to_string())),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) => "string",
+            Value::Bytes(_) => "bytes",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+        }
+    }
+
+    ///Rough number of bytes the value takes up, for sizing tables
+    pub fn approx_size(&self) -> usize {
+        match self {
+            Value::Null | Value::Bool(_) => 1,
+            Value::Int(_) | Value::Float(_) => 8,
+            Value::Str(s) => s.len(),
+            Value::Bytes(bytes) => bytes.len(),
+            Value::List(items) => items.iter().map(Value::approx_size).sum(),
+            Value::Map(entries) => entries
+                .iter()
+                .map(|(key, val)| key.len() + val.approx_size())
+                .sum(),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+///Values display as literals that parse back to the same value, so `"42"` and `42` differ
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            //debug formatting keeps the `.0` on whole floats
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Str(s) => write_quoted(f, s),
+            Value::Bytes(bytes) => {
+                write!(f, "x'")?;
+                for byte in bytes {
+                    write!(f, "{:02x}", byte)?;
+                }
+                write!(f, "'")
+            }
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, val)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write_quoted(f, key)?;
+                    write!(f, ": {}", val)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_quoted(f: &mut std::fmt::Formatter<'_>, s: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '\r' => write!(f, "\\r")?,
+            '\0' => write!(f, "\\0")?,
+            c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.to_string())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Value::Int(i)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Value::Float(x)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Self {
+        Value::Bytes(bytes)
+    }
+}
+
+impl PartialEq<str> for Value {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == Some(other)
+    }
+}
+
+impl PartialEq<&str> for Value {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == Some(*other)
+    }
+}
+
+impl PartialEq<String> for Value {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == Some(other.as_str())
+    }
+}
+
+///Parse a complete literal, eg `[1, "two", {three: 3.0}]`
+fn parse_literal(text: &str) -> Result<Value, ParseError> {
+    let mut chars = text.chars();
+    let value = parse_value(&mut chars)?;
+    skip_whitespace(&mut chars);
+    if !chars.as_str().is_empty() {
+        return Err(ParseError::invalid_token(format!(
+            "Unexpected '{}' after value in {}",
+            chars.as_str(),
+            text
+        )));
+    }
+    Ok(value)
+}
+
+fn parse_value(chars: &mut Chars) -> Result<Value, ParseError> {
+    skip_whitespace(chars);
+    match peek(chars) {
+        Some('[') => {
+            chars.next();
+            let mut items = Vec::new();
+            parse_sequence(chars, ']', |chars| {
+                items.push(parse_value(chars)?);
+                Ok(())
+            })?;
+            Ok(Value::List(items))
+        }
+        Some('{') => {
+            chars.next();
+            let mut entries = BTreeMap::new();
+            parse_sequence(chars, '}', |chars| {
+                let key = match parse_value(chars)? {
+                    Value::Str(key) => key,
+                    //`{1: a}` is fine, the key is the text we saw
+                    other => other.to_string(),
+                };
+                skip_whitespace(chars);
+                if chars.next() != Some(':') {
+                    return Err(ParseError::invalid_token(format!(
+                        "Expected ':' after map key {}",
+                        key
+                    )));
+                }
+                entries.insert(key, parse_value(chars)?);
+                Ok(())
+            })?;
+            Ok(Value::Map(entries))
+        }
+        Some('"') => {
+            chars.next();
+            let mut s = String::new();
+            read_double_quoted(chars, &mut s)?;
+            Ok(Value::Str(s))
+        }
+        Some('\'') => {
+            chars.next();
+            Ok(Value::Str(read_single_quoted(chars)?))
+        }
+        Some(c) => {
+            let word = take_word(chars);
+            if word.is_empty() {
+                return Err(ParseError::invalid_token(format!("Unexpected '{}'", c)));
+            }
+            if peek(chars) == Some('\'') {
+                chars.next();
+                let literal = read_single_quoted(chars)?;
+                return match word.as_str() {
+                    "x" | "X" => Ok(Value::Bytes(decode_hex(&literal)?)),
+                    "b64" | "B64" => Ok(Value::Bytes(decode_base64(&literal)?)),
+                    _ => Err(ParseError::invalid_token(format!(
+                        "Unknown literal prefix: {}",
+                        word
+                    ))),
+                };
+            }
+            Ok(scalar(word))
+        }
+        None => Err(ParseError::invalid_token("Expected a value".to_string())),
+    }
+}
+
+///Comma separated items up to `close`, a trailing comma is allowed
+fn parse_sequence(
+    chars: &mut Chars,
+    close: char,
+    mut item: impl FnMut(&mut Chars) -> Result<(), ParseError>,
+) -> Result<(), ParseError> {
+    loop {
+        skip_whitespace(chars);
+        if peek(chars) == Some(close) {
+            chars.next();
+            return Ok(());
+        }
+        item(chars)?;
+        skip_whitespace(chars);
+        match chars.next() {
+            Some(',') => continue,
+            Some(c) if c == close => return Ok(()),
+            Some(c) => {
+                return Err(ParseError::invalid_token(format!(
+                    "Expected ',' or '{}', got '{}'",
+                    close, c
+                )));
+            }
+            None => {
+                return Err(ParseError::invalid_token(format!(
+                    "Missing closing '{}'",
+                    close
+                )));
+            }
+        }
+    }
+}
+
+///Bare words are null, bools and numbers, or else strings. A word is only a number
+///if it reads back the same, so `02134` or `+15551234` keep their text.
+fn scalar(word: String) -> Value {
+    match word.as_str() {
+        "null" => return Value::Null,
+        "true" => return Value::Bool(true),
+        "false" => return Value::Bool(false),
+        _ => {}
+    }
+    if let Ok(i) = word.parse::<i64>()
+        && i.to_string() == word
+    {
+        return Value::Int(i);
+    }
+    //f64 parsing also takes `inf` and `NaN`, we want those as text
+    let numeric = word.chars().any(|c| c.is_ascii_digit())
+        && word
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'));
+    if numeric
+        && let Ok(x) = word.parse::<f64>()
+        && format!("{:?}", x) == word
+    {
+        return Value::Float(x);
+    }
+    Value::Str(word)
+}
+
+fn take_word(chars: &mut Chars) -> String {
+    let mut word = String::new();
+    while let Some(c) = peek(chars) {
+        if c.is_whitespace() || matches!(c, ',' | ':' | '[' | ']' | '{' | '}' | '"' | '\'') {
+            break;
+        }
+        word.push(c);
+        chars.next();
+    }
+    word
+}
+
+fn skip_whitespace(chars: &mut Chars) {
+    while peek(chars).is_some_and(char::is_whitespace) {
+        chars.next();
+    }
+}
+
+fn peek(chars: &Chars) -> Option<char> {
+    chars.clone().next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_bare_scalars() {
+        assert_eq!(Value::from_bare("null"), Ok(Value::Null));
+        assert_eq!(Value::from_bare("true"), Ok(Value::Bool(true)));
+        assert_eq!(Value::from_bare("-42"), Ok(Value::Int(-42)));
+        assert_eq!(Value::from_bare("-0.25"), Ok(Value::Float(-0.25)));
+        assert_eq!(Value::from_bare("alice"), Ok(Value::from("alice")));
+        assert_eq!(Value::from_bare("inf"), Ok(Value::from("inf")));
+        assert_eq!(
+            Value::from_bare("2024-01-01"),
+            Ok(Value::from("2024-01-01"))
+        );
+        //numbers that wouldn't read back as typed stay text
+        assert_eq!(Value::from_bare("02134"), Ok(Value::from("02134")));
+        assert_eq!(Value::from_bare("+15551234"), Ok(Value::from("+15551234")));
+        assert_eq!(Value::from_bare("-0"), Ok(Value::from("-0")));
+        assert_eq!(Value::from_bare("2.50"), Ok(Value::from("2.50")));
+        assert_eq!(Value::from_bare("2.5e3"), Ok(Value::from("2.5e3")));
+    }
+
+    #[test]
+    fn test_from_bare_collections() {
+        let value = Value::from_bare(r#"[1, "two words", x'ff00', {b: [], a: 1.5},]"#).unwrap();
+        let mut map = BTreeMap::new();
+        map.insert("a".to_string(), Value::Float(1.5));
+        map.insert("b".to_string(), Value::List(Vec::new()));
+        assert_eq!(
+            value,
+            Value::List(vec![
+                Value::Int(1),
+                Value::from("two words"),
+                Value::Bytes(vec![0xff, 0x00]),
+                Value::Map(map),
+            ])
+        );
+        assert!(Value::from_bare("[1, 2").is_err());
+        assert!(Value::from_bare("{a 1}").is_err());
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        let text = r#"[null, true, 3, 1.0, "say \"hi\"\n", x'00ff', {"k": ["v"]}]"#;
+        let value = Value::from_bare(text).unwrap();
+        assert_eq!(value.to_string(), text);
+        assert_eq!(Value::from_bare(&value.to_string()).unwrap(), value);
+    }
+}
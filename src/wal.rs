@@ -1,4 +1,14 @@
-use bincode::{Decode, Encode};
+use bincode::{Decode, Encode, config, decode_from_slice, encode_to_vec};
+
+use crate::{err_types::RustyDbErr, table_meta::TableOptions, value::Value};
+
+///Every WAL file starts with the magic bytes followed by a little endian format version
+///and generation. WALs from before the header have no magic and length-only framing.
+pub const WAL_MAGIC: &[u8; 6] = b"RDBWAL";
+pub const WAL_VERSION: u16 = 1;
+pub const WAL_HEADER_LEN: usize = WAL_MAGIC.len() + 2 + 8;
+///Each record is framed as payload length, crc32 of the payload, then the payload
+const RECORD_HEADER_LEN: usize = 8;
 
 ///Write Ahead Log entry
 #[derive(Debug, Clone, Encode, Decode)]
@@ -6,7 +16,7 @@ pub enum WalEntry {
     Put {
         table: String,
         key: String,
-        val: String,
+        val: Value,
     },
     Delete {
         table: String,
@@ -18,15 +28,390 @@ pub enum WalEntry {
     DropTable {
         table: String,
     },
+    ///Set (or with `None` clear) the unix millis a key expires at
+    Expire {
+        table: String,
+        key: String,
+        expires_at: Option<u64>,
+    },
+    ///A committed group of entries, replayed all together or not at all
+    Transaction {
+        entries: Vec<WalEntry>,
+    },
+    //new variants go last, bincode numbers them in order
+    ///Move a table, its keys and their expiries to a new name
+    RenameTable {
+        from: String,
+        to: String,
+    },
+    ///Remove every key from a table but keep the table
+    TruncateTable {
+        table: String,
+    },
+    ///Create `to` holding a copy of `from`, expiries included
+    CopyTable {
+        from: String,
+        to: String,
+    },
+    ///Replace a table's options
+    SetTableOptions {
+        table: String,
+        options: TableOptions,
+    },
 }
 
 impl WalEntry {
-    pub fn table_name(&self) -> &str {
+    ///Table the entry applies to, `None` for entries that span tables
+    pub fn table_name(&self) -> Option<&str> {
         match self {
-            WalEntry::Put { table, .. } => table,
-            WalEntry::Delete { table, .. } => table,
-            WalEntry::CreateTable { table } => table,
-            WalEntry::DropTable { table } => table,
+            WalEntry::Put { table, .. } => Some(table),
+            WalEntry::Delete { table, .. } => Some(table),
+            WalEntry::CreateTable { table } => Some(table),
+            WalEntry::DropTable { table } => Some(table),
+            WalEntry::Expire { table, .. } => Some(table),
+            WalEntry::TruncateTable { table } => Some(table),
+            WalEntry::SetTableOptions { table, .. } => Some(table),
+            WalEntry::Transaction { .. }
+            | WalEntry::RenameTable { .. }
+            | WalEntry::CopyTable { .. } => None,
+        }
+    }
+}
+
+///Entries of WALs from before typed values, when every value was a string. The
+///unframed WALs from before the header hold the first four variants.
+#[derive(Decode)]
+#[cfg_attr(test, derive(Encode))]
+enum LegacyWalEntry {
+    Put {
+        table: String,
+        key: String,
+        val: String,
+    },
+    Delete {
+        table: String,
+        key: String,
+    },
+    CreateTable {
+        table: String,
+    },
+    DropTable {
+        table: String,
+    },
+}
+
+impl From<LegacyWalEntry> for WalEntry {
+    fn from(legacy: LegacyWalEntry) -> Self {
+        match legacy {
+            LegacyWalEntry::Put { table, key, val } => WalEntry::Put {
+                table,
+                key,
+                val: Value::Str(val),
+            },
+            LegacyWalEntry::Delete { table, key } => WalEntry::Delete { table, key },
+            LegacyWalEntry::CreateTable { table } => WalEntry::CreateTable { table },
+            LegacyWalEntry::DropTable { table } => WalEntry::DropTable { table },
         }
     }
 }
+
+///A decoded WAL record
+#[derive(Debug, Clone)]
+pub struct WalRecord {
+    pub entry: WalEntry,
+    ///unix millis the record was written, `None` in WALs from before the header
+    pub written_at: Option<u64>,
+}
+
+///What replaying a WAL file found
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WalReplay {
+    ///Number of records applied
+    pub entries: usize,
+    ///Length of the WAL up to the end of the last good record
+    pub valid_len: u64,
+    ///Bytes after the last good record that were thrown away
+    pub discarded_bytes: u64,
+    ///Why replay stopped early, if it did
+    pub corruption: Option<String>,
+    ///Format version from the WAL header, 0 if the file had none
+    pub version: u16,
+    ///Generation from the WAL header, `None` if the file was empty. WALs from before
+    ///the header count as generation 0.
+    pub generation: Option<u64>,
+}
+
+///Header of a new WAL. Every checkpoint starts a WAL one generation on, so a
+///snapshot can tell which WALs it already holds.
+pub fn wal_header(generation: u64) -> [u8; WAL_HEADER_LEN] {
+    let mut header = [0; WAL_HEADER_LEN];
+    header[..WAL_MAGIC.len()].copy_from_slice(WAL_MAGIC);
+    header[WAL_MAGIC.len()..WAL_MAGIC.len() + 2].copy_from_slice(&WAL_VERSION.to_le_bytes());
+    header[WAL_MAGIC.len() + 2..].copy_from_slice(&generation.to_le_bytes());
+    header
+}
+
+///Frame an entry written at `written_at` (unix millis) as a checksummed record
+///ready to append to the WAL
+pub fn encode_record(entry: &WalEntry, written_at: u64) -> Result<Vec<u8>, RustyDbErr> {
+    let payload = encode_to_vec((written_at, entry), config::standard())
+        .map_err(|e| RustyDbErr::serialization("encoding WAL entry", e))?;
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+///Decode the records of a WAL file, stopping at the first torn or corrupt one.
+///Only a file that is not a WAL at all (wrong magic or version) is an error.
+pub fn decode_records(data: &[u8]) -> Result<(Vec<WalRecord>, WalReplay), RustyDbErr> {
+    let mut replay = WalReplay::default();
+    let mut records = Vec::new();
+
+    if data.is_empty() {
+        return Ok((records, replay));
+    }
+    let magic_len = WAL_MAGIC.len().min(data.len());
+    if data[..magic_len] != WAL_MAGIC[..magic_len] {
+        return decode_legacy_records(data);
+    }
+    if data.len() < WAL_HEADER_LEN {
+        //crashed while writing the header of a fresh file
+        replay.discarded_bytes = data.len() as u64;
+        replay.corruption = Some("Torn WAL header".to_string());
+        return Ok((records, replay));
+    }
+    let version = u16::from_le_bytes([data[WAL_MAGIC.len()], data[WAL_MAGIC.len() + 1]]);
+    if version != WAL_VERSION {
+        return Err(RustyDbErr::Serialization {
+            context: format!(
+                "Unsupported WAL version {}, expected {}",
+                version, WAL_VERSION
+            ),
+            source: None,
+        });
+    }
+    replay.version = version;
+    let mut generation = [0; 8];
+    generation.copy_from_slice(&data[WAL_MAGIC.len() + 2..WAL_HEADER_LEN]);
+    replay.generation = Some(u64::from_le_bytes(generation));
+
+    let mut offset = WAL_HEADER_LEN;
+    while offset < data.len() {
+        match decode_record(data, offset) {
+            Ok((record, next)) => {
+                records.push(record);
+                offset = next;
+            }
+            Err(why) => {
+                replay.discarded_bytes = (data.len() - offset) as u64;
+                replay.corruption = Some(why);
+                break;
+            }
+        }
+    }
+    replay.valid_len = offset as u64;
+    replay.entries = records.len();
+    Ok((records, replay))
+}
+
+///Decode a WAL from before the header, whose records are framed by their length
+///alone. With no magic to go on the file is only taken for one if its first record
+///decodes, after that a bad record is a torn tail like in any other WAL.
+fn decode_legacy_records(data: &[u8]) -> Result<(Vec<WalRecord>, WalReplay), RustyDbErr> {
+    let mut replay = WalReplay {
+        generation: Some(0),
+        ..WalReplay::default()
+    };
+    let mut records = Vec::new();
+
+    let mut offset = 0;
+    while offset < data.len() {
+        match decode_legacy_record(data, offset) {
+            Ok((record, next)) => {
+                records.push(record);
+                offset = next;
+            }
+            Err(why) if records.is_empty() => {
+                return Err(RustyDbErr::Serialization {
+                    context: format!(
+                        "WAL file does not start with the RDBWAL magic or a legacy record: {}",
+                        why
+                    ),
+                    source: None,
+                });
+            }
+            Err(why) => {
+                replay.discarded_bytes = (data.len() - offset) as u64;
+                replay.corruption = Some(why);
+                break;
+            }
+        }
+    }
+    replay.valid_len = offset as u64;
+    replay.entries = records.len();
+    Ok((records, replay))
+}
+
+///Decode the unframed record at `offset`, returning it and the offset of the next one
+fn decode_legacy_record(data: &[u8], offset: usize) -> Result<(WalRecord, usize), String> {
+    let Some(len) = data.get(offset..offset + 4) else {
+        return Err(format!("Torn record header at byte {}", offset));
+    };
+    let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    let start = offset + 4;
+    if start + len > data.len() {
+        return Err(format!("Torn record at byte {}", offset));
+    }
+    let payload = &data[start..start + len];
+    match decode_from_slice::<LegacyWalEntry, _>(payload, config::standard()) {
+        //without a checksum, a record that doesn't fill its length is garbage too
+        Ok((entry, used)) if used == len => Ok((
+            WalRecord {
+                entry: entry.into(),
+                written_at: None,
+            },
+            start + len,
+        )),
+        Ok(_) => Err(format!("Undecodable record at byte {}", offset)),
+        Err(e) => Err(format!("Undecodable record at byte {}: {}", offset, e)),
+    }
+}
+
+///Decode the record at `offset`, returning it and the offset of the next one
+fn decode_record(data: &[u8], offset: usize) -> Result<(WalRecord, usize), String> {
+    if offset + RECORD_HEADER_LEN > data.len() {
+        return Err(format!("Torn record header at byte {}", offset));
+    }
+    let field =
+        |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+    let len = field(offset) as usize;
+    let crc = field(offset + 4);
+    let start = offset + RECORD_HEADER_LEN;
+    if start + len > data.len() {
+        return Err(format!("Torn record at byte {}", offset));
+    }
+    let payload = &data[start..start + len];
+    if crc32fast::hash(payload) != crc {
+        return Err(format!("Checksum mismatch at byte {}", offset));
+    }
+    let ((written_at, entry), _) =
+        decode_from_slice::<(u64, WalEntry), _>(payload, config::standard())
+            .map_err(|e| format!("Undecodable record at byte {}: {}", offset, e))?;
+    let record = WalRecord {
+        entry,
+        written_at: Some(written_at),
+    };
+    Ok((record, start + len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(key: &str) -> WalEntry {
+        WalEntry::Put {
+            table: "t".to_string(),
+            key: key.to_string(),
+            val: Value::from("v"),
+        }
+    }
+
+    fn wal_of(entries: &[WalEntry]) -> Vec<u8> {
+        let mut data = wal_header(7).to_vec();
+        for entry in entries {
+            data.extend(encode_record(entry, 1).unwrap());
+        }
+        data
+    }
+
+    #[test]
+    fn test_decode_round_trip() {
+        let data = wal_of(&[put("a"), put("b")]);
+        let (entries, replay) = decode_records(&data).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(replay.entries, 2);
+        assert_eq!(replay.valid_len, data.len() as u64);
+        assert_eq!(replay.corruption, None);
+    }
+
+    #[test]
+    fn test_decode_stops_at_bit_flip() {
+        let mut data = wal_of(&[put("a"), put("b"), put("c")]);
+        let first_len = wal_of(&[put("a")]).len();
+        //flip a bit in the payload of the second record
+        data[first_len + RECORD_HEADER_LEN + 2] ^= 0x01;
+        let (entries, replay) = decode_records(&data).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(replay.valid_len, first_len as u64);
+        assert_eq!(replay.discarded_bytes, (data.len() - first_len) as u64);
+        assert!(replay.corruption.unwrap().starts_with("Checksum mismatch"));
+    }
+
+    #[test]
+    fn test_decode_torn_tail() {
+        let data = wal_of(&[put("a"), put("b")]);
+        let (entries, replay) = decode_records(&data[..data.len() - 1]).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(replay.corruption.unwrap().starts_with("Torn record"));
+        let (entries, replay) = decode_records(&data[..3]).unwrap();
+        assert!(entries.is_empty());
+        assert_eq!(replay.valid_len, 0);
+        //magic and version made it but the generation didn't
+        let (entries, replay) = decode_records(&data[..WAL_HEADER_LEN - 1]).unwrap();
+        assert!(entries.is_empty());
+        assert_eq!(replay.corruption.as_deref(), Some("Torn WAL header"));
+    }
+
+    #[test]
+    fn test_decode_rejects_foreign_file() {
+        assert!(decode_records(b"definitely not a wal").is_err());
+        let mut data = wal_of(&[]);
+        data[WAL_MAGIC.len()] = 9;
+        assert!(decode_records(&data).is_err());
+    }
+
+    #[test]
+    fn test_decode_legacy_wal() {
+        let legacy = [
+            LegacyWalEntry::CreateTable {
+                table: "t".to_string(),
+            },
+            LegacyWalEntry::Put {
+                table: "t".to_string(),
+                key: "a".to_string(),
+                val: "v".to_string(),
+            },
+        ];
+        //the baseline framed records by their length alone
+        let mut data = Vec::new();
+        for entry in &legacy {
+            let payload = encode_to_vec(entry, config::standard()).unwrap();
+            data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            data.extend_from_slice(&payload);
+        }
+        let (records, replay) = decode_records(&data).unwrap();
+        assert_eq!(replay.version, 0);
+        assert_eq!(replay.generation, Some(0));
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].written_at, None);
+        assert!(matches!(
+            &records[1].entry,
+            WalEntry::Put { val: Value::Str(val), .. } if val == "v"
+        ));
+        let (records, replay) = decode_records(&data[..data.len() - 1]).unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(replay.corruption.unwrap().starts_with("Torn record"));
+    }
+
+    #[test]
+    fn test_decode_header() {
+        let (records, replay) = decode_records(&wal_of(&[put("a")])).unwrap();
+        assert_eq!(replay.version, WAL_VERSION);
+        assert_eq!(replay.generation, Some(7));
+        assert_eq!(records[0].written_at, Some(1));
+        let (_, replay) = decode_records(&[]).unwrap();
+        assert_eq!(replay.generation, None);
+    }
+}